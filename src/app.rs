@@ -1,51 +1,344 @@
 use std::{
-    collections::HashMap,
-    sync::mpsc::{self, Receiver, Sender},
+    env, fs,
+    io::{self, Write},
+    ops::Range,
+    path::Path,
+    process::{self, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
     thread,
     time::Duration,
 };
 
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
-use ratatui::{backend::Backend, layout::Rect, widgets::ListState, Terminal};
-use ratatui_image::{picker::Picker, protocol::StatefulProtocol, Resize};
+use clap::ValueEnum;
+use crossterm::{
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    execute,
+    terminal::{enable_raw_mode, EnterAlternateScreen},
+};
+use ratatui::{
+    backend::Backend,
+    layout::{Position, Rect},
+    widgets::ListState,
+    Terminal,
+};
+use ratatui_image::{
+    picker::{Picker, ProtocolType},
+    protocol::StatefulProtocol,
+    Resize,
+};
+use serde::Deserialize;
 
 use crate::{
+    layout::LayoutCache,
     models::{
         book::Book,
-        reading_position::{calculate_book_hash, ReadingPosition},
+        bookmark::{Bookmark, Bookmarks},
+        config::{Config, StartScreen},
+        highlight::{Highlight, HighlightColor, Highlights},
+        history::{History, HistorySort},
+        identity::book_id,
+        image_cache::{image_id_parts, DecodedImages, MemoryCache},
+        keymap::{Action, Context},
+        reading_position::{Anchor, ReadingPosition},
+        reading_speed::ReadingSpeed,
+        search::{search, SearchHit},
+        theme::Theme,
+        typography::{Typography, MIN_WIDTH},
     },
     ui::ui,
     widgets::custom_thread_image::ThreadProtocol,
 };
 
+// How often the input thread checks whether it is paused. The note editor waits for two intervals
+// before it takes the terminal, so the 1 second that the input was polled for would delay it by 2
+// seconds. A shorter poll costs nothing, as it returns as soon as there is an event.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Clone)]
 pub struct ReadingRecord {
     page: String,
     offset: usize,
 }
 
+// The search prompt shown over the reading screen
+#[derive(Default)]
+pub struct SearchPrompt {
+    pub query: String,
+    pub regex: bool,
+    pub error: Option<String>,
+    // Whether the query was searched, the results are kept until the next search
+    pub searched: bool,
+    // The selected result, None while the query is being edited
+    pub results: ListState,
+}
+
+// A cursor over the text of a chapter, which selects a passage once the anchor is set. The
+// positions are in the text of the page, see Page::text.
+pub struct Selection {
+    pub page: String,
+    pub cursor: usize,
+    pub anchor: Option<usize>,
+}
+
+impl Selection {
+    pub fn range(&self) -> Range<usize> {
+        let anchor = self.anchor.unwrap_or(self.cursor);
+
+        anchor.min(self.cursor)..anchor.max(self.cursor) + 1
+    }
+}
+
+// The prompt to pick the color and write the note of a new highlight
+pub struct HighlightPrompt {
+    pub page: String,
+    pub range: Range<usize>,
+    pub color: HighlightColor,
+    pub note: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Zoom {
+    Fit,
+    Actual,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReadingMode {
+    // The chapter is scrolled line by line
+    Scroll,
+    // The chapter is split into pages that fit the screen, like an e-reader
+    Paged,
+    // The chapters are scrolled as one document, separated by a divider
+    Continuous,
+}
+
 pub enum Screen {
+    Library {
+        list_state: ListState,
+        filter: String,
+        // Whether the filter is being edited
+        filtering: bool,
+        sort: HistorySort,
+        // The book that was being read, None if no book is open
+        prev_screen: Option<ReadingRecord>,
+    },
+    Bookmarks {
+        list_state: ListState,
+        prev_screen: ReadingRecord,
+    },
     Info {
+        // The selected entry of the filtered table of contents
         toc_state: ListState,
+        filter: String,
+        // The TOC entries matching the filter, see Book::filter_toc, which are updated when the
+        // filter changes
+        entries: Vec<(usize, Vec<usize>)>,
+        // Whether the filter is being edited
+        filtering: bool,
+        // The TOC entry of the reading position, selected when there is no filter
+        reading_entry: Option<usize>,
         prev_screen: Option<ReadingRecord>,
     },
     Reading {
         page: String,
         offset: usize,
     },
+    Viewer {
+        images: Vec<String>,
+        index: usize,
+        zoom: Zoom,
+        // The offset in pixels of the top-left corner of the image shown at actual size
+        pan: (u32, u32),
+        prev_screen: ReadingRecord,
+    },
+}
+
+impl Screen {
+    // The keys of the screen
+    fn context(&self) -> Context {
+        match self {
+            Screen::Library { .. } => Context::Library,
+            Screen::Bookmarks { .. } => Context::Bookmarks,
+            Screen::Info { .. } => Context::Info,
+            Screen::Reading { .. } => Context::Reading,
+            Screen::Viewer { .. } => Context::Viewer,
+        }
+    }
+
+    // The book info, with the TOC entry of the reading position selected. The position is the
+    // one in the page of the previous screen.
+    fn info(book: &Book, prev_screen: Option<ReadingRecord>, position: usize) -> Screen {
+        let reading_entry = prev_screen
+            .as_ref()
+            .and_then(|x| book.toc_entry(&x.page, position));
+
+        Screen::Info {
+            toc_state: ListState::default().with_selected(reading_entry),
+            filter: String::new(),
+            entries: book.filter_toc(""),
+            filtering: false,
+            reading_entry,
+            prev_screen,
+        }
+    }
+}
+
+// The TOC entry selected when the filter of the book info is changed, which is the best match of
+// the filter, or the entry of the reading position without a filter
+fn toc_selection(
+    filter: &str,
+    entries: &[(usize, Vec<usize>)],
+    reading_entry: Option<usize>,
+) -> Option<usize> {
+    if filter.is_empty() {
+        reading_entry
+    } else {
+        (!entries.is_empty()).then_some(0)
+    }
+}
+
+// Where to start reading instead of the saved position
+pub enum StartAt {
+    // The chapter in reading order, counted from 1
+    Chapter(usize),
+    TocEntry(String),
+    Percent(f64),
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    Auto,
+    Kitty,
+    Sixel,
+    Iterm,
+    Halfblocks,
+    None,
+}
+
+impl ImageMode {
+    // Font size used when the terminal does not report one
+    const FALLBACK_FONT_SIZE: (u16, u16) = (8, 16);
+
+    // The query of ratatui-image keeps reading stdin after timing out if the terminal does not
+    // answer it, until it reads the reply to a device status report (DSR, "ESC [ 5 n"). Ask for
+    // one to end it instead of letting it swallow the key presses. The request is written raw, as
+    // crossterm has no command for it, and cursor::position waits for its own reply, which the
+    // query would read first.
+    fn end_query() {
+        let mut stdout = io::stdout();
+        if stdout.write_all(b"\x1b[5n").and(stdout.flush()).is_ok() {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    // Returns None when images should be shown as text placeholders
+    fn create_picker(self) -> Option<Picker> {
+        if self == ImageMode::None {
+            return None;
+        }
+
+        let queried = Picker::from_query_stdio().ok();
+        if queried.is_none() {
+            ImageMode::end_query();
+        }
+        let protocol_type = match self {
+            ImageMode::Kitty => ProtocolType::Kitty,
+            ImageMode::Sixel => ProtocolType::Sixel,
+            ImageMode::Iterm => ProtocolType::Iterm2,
+            ImageMode::Halfblocks => ProtocolType::Halfblocks,
+            // Fall back to halfblocks, which every terminal with colors can show
+            _ => queried
+                .map(|p| p.protocol_type())
+                .unwrap_or(ProtocolType::Halfblocks),
+        };
+
+        let mut picker =
+            queried.unwrap_or_else(|| Picker::from_fontsize(ImageMode::FALLBACK_FONT_SIZE));
+        picker.set_protocol_type(protocol_type);
+
+        Some(picker)
+    }
+}
+
+// What a click on the reading screen opens
+#[derive(Clone)]
+pub enum ClickTarget {
+    // The path of the page that a link goes to
+    Link(String),
+    Image { page: String, path: String },
 }
 
 enum AppEvent {
     KeyEvent(KeyEvent),
-    Redraw(String, StatefulProtocol),
+    Mouse(MouseEvent),
+    Redraw(String, Box<StatefulProtocol>),
 }
 
 pub struct App {
     pub book: Book,
+    pub config: Config,
+    pub theme: Theme,
+    theme_name: String,
+    // Shown until the next key press, like the result of reloading the config. An error is shown
+    // as Err.
+    pub message: Option<Result<String, String>>,
+    pub history: History,
     pub current_screen: Screen,
-    pub picker: Picker,
-    pub image_state: HashMap<String, ThreadProtocol>,
+    // None if images are shown as text placeholders
+    pub picker: Option<Picker>,
+    pub image_state: MemoryCache<ThreadProtocol>,
+    pub decoded_images: DecodedImages,
+    pub layouts: LayoutCache,
     pub tx_worker: Sender<(String, StatefulProtocol, Resize, Rect)>,
+    // The image closest to the reading viewport when it was last rendered, with its chapter
+    pub nearest_image: Option<(String, String)>,
+    // The links and the images on the reading screen when it was last rendered
+    pub click_targets: Vec<(Rect, ClickTarget)>,
+    // The image on the reading screen chosen to be opened in the viewer instead of the nearest
+    // one, as an index of visible_images. It is dropped by any other key.
+    pub chosen_image: Option<usize>,
+    // The scrollbar of the reading screen and the offset at its bottom, None if the chapter fits
+    // the screen
+    pub scrollbar: Option<(Rect, usize)>,
+    // Whether the scrollbar is being dragged
+    dragging: bool,
+    // The rows of the table of contents on the info screen when it was last rendered
+    pub toc_area: Rect,
+    pub mode: ReadingMode,
+    pub reading_speed: ReadingSpeed,
+    pub search_prompt: Option<SearchPrompt>,
+    // The results of the last search, which are highlighted while reading
+    pub search_hits: Vec<SearchHit>,
+    pub current_hit: Option<usize>,
+    pub bookmarks: Bookmarks,
+    // The name of the bookmark being added
+    pub bookmark_prompt: Option<String>,
+    pub highlights: Highlights,
+    pub selection: Option<Selection>,
+    pub highlight_prompt: Option<HighlightPrompt>,
+    // Whether the note of the highlight prompt should be opened in the editor
+    edit_note: bool,
+    // The input thread stops reading the terminal while another program uses it
+    input_paused: Arc<AtomicBool>,
+    pub typography: Typography,
+    // The width of the reading screen, which the column of text is centered in
+    text_width: u16,
+    // The area that the chapters were last laid out for
+    pub reading_area: Rect,
+    // The position in the text of the page to scroll to once the page is laid out
+    pending_position: Option<usize>,
+    // The rows that the pages of the chapter start at when it was last rendered
+    pub pages: Vec<usize>,
+    // The chapter that the cached images are kept for
+    chapter: Option<String>,
     exit: bool,
     rec_main: Receiver<AppEvent>,
     book_hash: String,
@@ -53,15 +346,60 @@ pub struct App {
 
 impl App {
     pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) {
+        App::capture_mouse(self.config.mouse);
         while !self.exit {
             terminal.draw(|f| ui(f, self)).unwrap();
             self.handle_event();
+            self.evict_left_chapter();
+
+            if self.edit_note {
+                self.edit_note = false;
+                self.open_note_editor(terminal);
+            }
         }
+        // The errors of saving when quitting are left in the message, to be printed after the
+        // terminal is restored
         self.save_reading_position();
+        let result = self.reading_speed.save();
+        self.report(result, "reading speed");
+        App::capture_mouse(false);
+    }
+
+    // Show the error of saving a file instead of crashing, as the book can still be read
+    fn report(&mut self, result: io::Result<()>, name: &str) {
+        if let Err(err) = result {
+            self.message = Some(Err(format!("Could not save the {}: {}", name, err)));
+        }
+    }
+
+    // Show the error of loading a file and use the default instead of crashing
+    fn load_or_report<T: Default>(&mut self, result: io::Result<T>, name: &str) -> T {
+        result.unwrap_or_else(|err| {
+            self.message = Some(Err(format!("Could not load the {}: {}", name, err)));
+            T::default()
+        })
+    }
+
+    // The terminal only reports the mouse while it is captured, and cannot select text then
+    fn capture_mouse(enabled: bool) {
+        if enabled {
+            execute!(io::stdout(), EnableMouseCapture).unwrap();
+        } else {
+            execute!(io::stdout(), DisableMouseCapture).unwrap();
+        }
     }
 
-    pub fn new(path: &str) -> App {
-        let picker = Picker::from_query_stdio().unwrap();
+    // image_cache_size is the memory budget in bytes for the decoded and encoded images
+    // The library is shown if no path is given
+    // The book is opened before the app, so that a book that cannot be read is reported before the
+    // terminal is taken over
+    pub fn new(
+        book: Option<(&str, Book)>,
+        config: Config,
+        image_mode: ImageMode,
+        image_cache_size: usize,
+    ) -> App {
+        let picker = image_mode.create_picker();
 
         let (tx_worker, rec_worker) = mpsc::channel::<(String, StatefulProtocol, Resize, Rect)>();
         let (tx_main, rec_main) = mpsc::channel();
@@ -70,55 +408,322 @@ impl App {
         thread::spawn(move || loop {
             if let Ok((id, mut protocol, resize, area)) = rec_worker.recv() {
                 protocol.resize_encode(&resize, None, area);
-                tx_main_render.send(AppEvent::Redraw(id, protocol)).unwrap();
+                tx_main_render
+                    .send(AppEvent::Redraw(id, Box::new(protocol)))
+                    .unwrap();
             }
         });
 
+        let input_paused = Arc::new(AtomicBool::new(false));
+        let input_paused_thread = input_paused.clone();
         thread::spawn(move || -> Result<(), std::io::Error> {
             loop {
-                if ratatui::crossterm::event::poll(Duration::from_millis(1000)).unwrap() {
-                    if let Event::Key(key) = event::read().unwrap() {
-                        tx_main.send(AppEvent::KeyEvent(key)).unwrap();
+                if input_paused_thread.load(Ordering::Relaxed) {
+                    thread::sleep(INPUT_POLL_INTERVAL);
+                    continue;
+                }
+
+                if ratatui::crossterm::event::poll(INPUT_POLL_INTERVAL).unwrap() {
+                    match event::read().unwrap() {
+                        Event::Key(key) => tx_main.send(AppEvent::KeyEvent(key)).unwrap(),
+                        Event::Mouse(mouse) => tx_main.send(AppEvent::Mouse(mouse)).unwrap(),
+                        _ => (),
                     }
                 }
             }
         });
 
-        let book = Book::new(path);
-        let book_hash = calculate_book_hash(path).expect("A valid book hash");
+        // The config is validated when it is loaded
+        let theme = config.theme(&config.theme).unwrap();
+        let typography = Typography::new(&config);
+
+        let mut app = App {
+            book: Book::default(),
+            theme,
+            theme_name: config.theme.clone(),
+            config,
+            message: None,
+            history: History::load(),
+            exit: false,
+            current_screen: Screen::Library {
+                list_state: ListState::default().with_selected(Some(0)),
+                filter: String::new(),
+                filtering: false,
+                sort: HistorySort::Recent,
+                prev_screen: None,
+            },
+            tx_worker,
+            rec_main,
+            picker,
+            // The budget is shared equally between the decoded and the encoded images
+            image_state: MemoryCache::new(image_cache_size / 2),
+            decoded_images: DecodedImages::new(image_cache_size / 2),
+            layouts: LayoutCache::new(typography),
+            typography,
+            text_width: 0,
+            nearest_image: None,
+            click_targets: vec![],
+            chosen_image: None,
+            scrollbar: None,
+            dragging: false,
+            toc_area: Rect::default(),
+            mode: ReadingMode::Scroll,
+            reading_area: Rect::default(),
+            pending_position: None,
+            reading_speed: ReadingSpeed::load(),
+            search_prompt: None,
+            search_hits: vec![],
+            current_hit: None,
+            bookmarks: Bookmarks::default(),
+            bookmark_prompt: None,
+            highlights: Highlights::default(),
+            selection: None,
+            highlight_prompt: None,
+            edit_note: false,
+            input_paused,
+            pages: vec![],
+            chapter: None,
+            book_hash: String::new(),
+        };
+
+        if let Some((path, book)) = book {
+            app.open_book(path, book);
+        }
+
+        app
+    }
+
+    // Replace the book and everything kept for it with the book at the path. The current book is
+    // kept if the id of the book cannot be found.
+    fn open_book(&mut self, path: &str, book: Book) {
+        let book_hash = match book_id(path, book.identifier.as_deref()) {
+            Ok(book_hash) => book_hash,
+            Err(err) => {
+                self.message = Some(Err(format!("Could not open the book: {}", err)));
+                return;
+            }
+        };
+
+        self.history.open(path, &book_hash, &book);
+        let result = self.history.save();
+        self.report(result, "history");
+
+        let mut pending_position = None;
+        // A position in a chapter that is no longer in the book is ignored
+        let saved_position = ReadingPosition::load(&book_hash)
+            .ok()
+            .flatten()
+            .filter(|x| book.pages.contains_key(&x.page));
+
+        let current_screen = if let Some(position) = saved_position {
+            let offset = match position.anchor {
+                Anchor::Content(content, offset) => {
+                    let page = book.pages.get(&position.page).unwrap();
+                    pending_position = Some(page.position(content, offset));
+                    0
+                }
+                // The older versions saved the line, which is kept until the position is saved
+                Anchor::Line(line) => line,
+            };
 
-        let current_screen = if let Ok(Some(position)) = ReadingPosition::load(&book_hash) {
-            Screen::Reading {
-                page: position.page,
-                offset: position.offset,
+            match self.config.start_screen {
+                StartScreen::Reading => Screen::Reading {
+                    page: position.page,
+                    offset,
+                },
+                // The book info returns to the saved position. The line saved by the older
+                // versions is not laid out yet, so its entry is the first of the page.
+                StartScreen::Info => Screen::info(
+                    &book,
+                    Some(ReadingRecord {
+                        page: position.page,
+                        offset,
+                    }),
+                    pending_position.unwrap_or(0),
+                ),
             }
         } else {
-            Screen::Info {
-                toc_state: ListState::default(),
-                prev_screen: None,
+            Screen::info(&book, None, 0)
+        };
+
+        self.bookmarks = self.load_or_report(Bookmarks::load(&book_hash), "bookmarks");
+        self.typography = self
+            .load_or_report(Typography::load(&book_hash), "typography")
+            .unwrap_or_else(|| Typography::new(&self.config));
+        self.highlights = self.load_or_report(Highlights::load(&book_hash), "highlights");
+
+        self.book = book;
+        self.book_hash = book_hash;
+        self.current_screen = current_screen;
+        self.pending_position = pending_position;
+
+        // The images and the layouts of the previous book may have the same paths
+        self.image_state.retain(|_| false);
+        self.decoded_images.clear();
+        self.layouts = LayoutCache::new(self.typography);
+        self.nearest_image = None;
+        self.pages = vec![];
+        self.chapter = None;
+        self.search_hits = vec![];
+        self.current_hit = None;
+    }
+
+    pub fn start_at(&mut self, start: StartAt) -> Result<(), String> {
+        let (page, position) = match start {
+            StartAt::Chapter(chapter) => {
+                let page = chapter
+                    .checked_sub(1)
+                    .and_then(|x| self.book.order.get(x))
+                    .ok_or(format!(
+                        "chapter {} not found, the book has {} chapters",
+                        chapter,
+                        self.book.order.len()
+                    ))?;
+
+                (page.clone(), 0)
+            }
+            StartAt::TocEntry(name) => {
+                // Prefer an exact match to one that contains the name
+                let query = name.to_lowercase();
+                let entry = self
+                    .book
+                    .toc
+                    .iter()
+                    .find(|x| x.2.to_lowercase() == query)
+                    .or_else(|| {
+                        self.book
+                            .toc
+                            .iter()
+                            .find(|x| x.2.to_lowercase().contains(&query))
+                    })
+                    .ok_or(format!("no table of contents entry matches '{}'", name))?;
+
+                (entry.0.clone(), entry.1)
+            }
+            StartAt::Percent(percent) => {
+                let position = (self.book.length() as f64 * percent / 100.0) as usize;
+                let (page, position) = self
+                    .book
+                    .locate(position)
+                    .ok_or("the book has no chapters".to_string())?;
+
+                (page.clone(), position)
             }
         };
 
-        App {
-            book,
-            exit: false,
-            current_screen,
-            tx_worker,
-            rec_main,
-            picker,
-            image_state: HashMap::new(),
-            book_hash,
+        self.current_screen = Screen::Reading { page, offset: 0 };
+        self.pending_position = Some(position);
+
+        Ok(())
+    }
+
+    // Save the position in the book and show the library, which returns to the position
+    fn open_library(&mut self, prev_screen: Option<ReadingRecord>) {
+        if let Some(prev_screen) = &prev_screen {
+            self.current_screen = Screen::Reading {
+                page: prev_screen.page.clone(),
+                offset: prev_screen.offset,
+            };
+            self.save_reading_position();
         }
+
+        self.current_screen = Screen::Library {
+            list_state: ListState::default().with_selected(Some(0)),
+            filter: String::new(),
+            filtering: false,
+            sort: HistorySort::Recent,
+            prev_screen,
+        };
     }
 
-    fn save_reading_position(&self) {
-        if let Screen::Reading { page, offset } = &self.current_screen {
-            ReadingPosition::new(page.clone(), *offset)
-                .save(&self.book_hash)
-                .expect("Save reading position");
+    // The chapter being read, or the one to return to
+    fn current_chapter(&self) -> Option<&String> {
+        match &self.current_screen {
+            Screen::Reading { page, .. } => Some(page),
+            Screen::Viewer { prev_screen, .. } | Screen::Bookmarks { prev_screen, .. } => {
+                Some(&prev_screen.page)
+            }
+            Screen::Info { prev_screen, .. } | Screen::Library { prev_screen, .. } => {
+                prev_screen.as_ref().map(|x| &x.page)
+            }
         }
     }
 
+    // Free the images of a chapter once it is left
+    fn evict_left_chapter(&mut self) {
+        let current = self.current_chapter().cloned();
+        if current == self.chapter {
+            return;
+        }
+
+        if let Some(chapter) = &self.chapter {
+            // The images missing from the book are shown as placeholders, and are not viewed
+            let mut images = self.book.pages.get(chapter).unwrap().images();
+            images.retain(|x| self.book.images.contains_key(x));
+
+            for path in &images {
+                self.decoded_images.remove(path);
+            }
+            self.image_state
+                .retain(|id| !images.iter().any(|x| x == image_id_parts(id).0));
+        }
+
+        self.chapter = current;
+    }
+
+    // Lay out the chapters for the area, keeping the text at the top of the screen in view when
+    // the area changes
+    pub fn set_reading_area(&mut self, area: Rect) {
+        let Screen::Reading { page, offset } = &mut self.current_screen else {
+            return;
+        };
+        self.text_width = area.width;
+        let area = self.typography.column(area);
+
+        if area != self.reading_area && self.pending_position.is_none() {
+            self.pending_position = self
+                .layouts
+                .cached(page, self.reading_area)
+                .map(|layout| layout.position(*offset));
+        }
+        self.reading_area = area;
+
+        if let Some(position) = self.pending_position.take() {
+            *offset = self
+                .layouts
+                .get(&mut self.book, page, area, self.picker)
+                .row(position);
+        }
+    }
+
+    // The position is saved as the element and the offset in it of the text at the top of the
+    // screen, so that it does not depend on the size of the screen
+    fn save_reading_position(&mut self) {
+        let Screen::Reading { page, offset } = &self.current_screen else {
+            return;
+        };
+
+        let position = match self.pending_position {
+            Some(position) => position,
+            None => self
+                .layouts
+                .get(&mut self.book, page, self.reading_area, self.picker)
+                .position(*offset),
+        };
+        let current_page = self.book.pages.get(page).unwrap();
+        let (content, offset) = current_page.anchor(position);
+
+        let fraction = position as f64 / current_page.length.max(1) as f64;
+        let progress = self.book.position(page, fraction) as f64 / self.book.length().max(1) as f64;
+        self.history
+            .set_progress(&self.book_hash, progress.min(1.0));
+        let saved_history = self.history.save();
+        let saved_position =
+            ReadingPosition::new(page.clone(), content, offset).save(&self.book_hash);
+        self.report(saved_history, "history");
+        self.report(saved_position, "reading position");
+    }
+
     fn handle_event(&mut self) {
         let result = self.rec_main.try_recv();
         if result.is_err() {
@@ -128,120 +733,1183 @@ impl App {
 
         match result.unwrap() {
             AppEvent::Redraw(id, proto) => {
-                let state = self.image_state.get_mut(&id);
-
-                if state.is_some() {
-                    state.unwrap().set_protocol(proto);
+                if let Some(state) = self.image_state.get_mut(&id) {
+                    state.set_protocol(*proto);
                 }
             }
             AppEvent::KeyEvent(key) => {
                 if key.kind == KeyEventKind::Press {
-                    self.handle_keypress(key.code);
+                    self.handle_keypress(key);
+                }
+            }
+            // The moves are reported as well, which do nothing
+            AppEvent::Mouse(mouse) if mouse.kind != MouseEventKind::Moved => {
+                self.handle_mouse(mouse);
+            }
+            AppEvent::Mouse(_) => (),
+        }
+    }
+
+    // The next or the previous chapter of the page in the reading order
+    pub fn neighbour_chapter(&self, page: &str, forward: bool) -> Option<String> {
+        let current_index = self.book.order.iter().position(|x| x == page)?;
+
+        let index = if forward {
+            current_index + 1
+        } else {
+            current_index.checked_sub(1)?
+        };
+
+        self.book.order.get(index).cloned()
+    }
+
+    // The number of rows of the chapter when laid out for the reading area
+    pub fn chapter_height(&mut self, page: &str) -> usize {
+        self.layouts
+            .get(&mut self.book, page, self.reading_area, self.picker)
+            .height()
+    }
+
+    // Go to the next or the previous chapter in the reading order, at the offset
+    fn turn_chapter(&mut self, forward: bool, offset: usize) {
+        let Screen::Reading { page, .. } = &self.current_screen else {
+            return;
+        };
+
+        if let Some(page) = self.neighbour_chapter(page, forward) {
+            self.current_screen = Screen::Reading { page, offset };
+        }
+    }
+
+    // In continuous mode, move the reading position to the chapter at the top of the screen, and
+    // make sure that the screen is not scrolled beyond the end of the book. The row after the end
+    // of a chapter is the divider before the next one.
+    pub fn normalize_continuous_position(&mut self) {
+        let Screen::Reading { page, offset } = &self.current_screen else {
+            return;
+        };
+        let (mut page, mut offset) = (page.clone(), *offset);
+        let view_height = self.reading_area.height as usize;
+
+        // Skip the chapters that are scrolled past
+        loop {
+            let height = self.chapter_height(&page);
+
+            match self.neighbour_chapter(&page, true) {
+                Some(next) if offset > height => {
+                    offset -= height + 1;
+                    page = next;
+                }
+                Some(_) => break,
+                None => {
+                    offset = offset.min(height);
+                    break;
+                }
+            }
+        }
+
+        // Count the rows left until the end of the book, as far as the screen reaches
+        let mut rows = self.chapter_height(&page) - offset;
+        let mut chapter = page.clone();
+        while rows < view_height {
+            let Some(next) = self.neighbour_chapter(&chapter, true) else {
+                break;
+            };
+
+            rows += 1 + self.chapter_height(&next);
+            chapter = next;
+        }
+
+        // Scroll back so that the screen is filled
+        let mut missing = view_height.saturating_sub(rows);
+        while missing > 0 {
+            if offset >= missing {
+                offset -= missing;
+                break;
+            }
+
+            match self.neighbour_chapter(&page, false) {
+                Some(previous) => {
+                    missing -= offset + 1;
+                    offset = self.chapter_height(&previous);
+                    page = previous;
+                }
+                None => {
+                    offset = 0;
+                    break;
+                }
+            }
+        }
+
+        self.current_screen = Screen::Reading { page, offset };
+    }
+
+    // Flip to the next or the previous page, continuing into the neighbouring chapters
+    fn flip_page(&mut self, forward: bool) {
+        let Screen::Reading { offset, .. } = &mut self.current_screen else {
+            return;
+        };
+
+        let target = if forward {
+            self.pages.iter().find(|x| **x > *offset)
+        } else {
+            self.pages.iter().rev().find(|x| **x < *offset)
+        };
+
+        match target {
+            Some(target) => *offset = *target,
+            // The offset is moved to the last page when the chapter is rendered
+            None if forward => self.turn_chapter(true, 0),
+            None => self.turn_chapter(false, usize::MAX),
+        }
+    }
+
+    // Show the position in the text of the page on the reading screen, with the number of lines
+    // above it when scrolling. A page always starts at its top.
+    fn go_to(&mut self, page: String, position: usize, margin: usize) {
+        let row = self
+            .layouts
+            .get(&mut self.book, &page, self.reading_area, self.picker)
+            .row(position);
+
+        let offset = match self.mode {
+            ReadingMode::Paged => row,
+            _ => row.saturating_sub(margin),
+        };
+
+        self.current_screen = Screen::Reading { page, offset };
+    }
+
+    fn go_to_hit(&mut self, index: usize) {
+        let hit = &self.search_hits[index];
+
+        self.current_hit = Some(index);
+        self.go_to(
+            hit.page.clone(),
+            hit.start,
+            self.reading_area.height as usize / 3,
+        );
+    }
+
+    fn add_bookmark(&mut self, name: String) {
+        let Screen::Reading { page, offset } = &self.current_screen else {
+            return;
+        };
+        let page = page.clone();
+
+        let position = self
+            .layouts
+            .get(&mut self.book, &page, self.reading_area, self.picker)
+            .position(*offset);
+        let excerpt: String = self
+            .book
+            .pages
+            .get(&page)
+            .unwrap()
+            .text()
+            .chars()
+            .skip(position)
+            .take(80)
+            .collect();
+
+        self.bookmarks.bookmarks.push(Bookmark {
+            name: if name.trim().is_empty() {
+                self.book.pages.get(&page).unwrap().title.clone()
+            } else {
+                name.trim().to_string()
+            },
+            page,
+            position,
+            excerpt: excerpt.replace('\n', " ").trim().to_string(),
+        });
+        let result = self.bookmarks.save(&self.book_hash);
+        self.report(result, "bookmarks");
+    }
+
+    fn handle_bookmark_keypress(&mut self, code: KeyCode) {
+        let name = self.bookmark_prompt.as_mut().unwrap();
+
+        match code {
+            KeyCode::Esc => self.bookmark_prompt = None,
+            KeyCode::Enter => {
+                let name = self.bookmark_prompt.take().unwrap();
+                self.add_bookmark(name);
+            }
+            KeyCode::Backspace => {
+                name.pop();
+            }
+            KeyCode::Char(c) => name.push(c),
+            _ => (),
+        }
+    }
+
+    fn handle_search_keypress(&mut self, code: KeyCode) {
+        let prompt = self.search_prompt.as_mut().unwrap();
+
+        match code {
+            KeyCode::Esc => self.search_prompt = None,
+            KeyCode::Enter => match prompt.results.selected() {
+                Some(index) => {
+                    self.search_prompt = None;
+                    self.go_to_hit(index);
+                }
+                None => match search(&self.book, &prompt.query, prompt.regex) {
+                    Ok(hits) => {
+                        prompt.error = None;
+                        prompt.searched = true;
+                        prompt.results.select((!hits.is_empty()).then_some(0));
+                        self.search_hits = hits;
+                        self.current_hit = None;
+                    }
+                    Err(err) => prompt.error = Some(err.to_string()),
+                },
+            },
+            KeyCode::Tab => {
+                prompt.regex = !prompt.regex;
+                prompt.results.select(None);
+            }
+            KeyCode::Up if prompt.results.selected().is_some() => {
+                prompt.results.select_previous();
+            }
+            KeyCode::Down if prompt.results.selected().is_some() => {
+                let index = prompt.results.selected().unwrap();
+                prompt
+                    .results
+                    .select(Some((index + 1).min(self.search_hits.len() - 1)));
+            }
+            KeyCode::Backspace => {
+                prompt.query.pop();
+                prompt.results.select(None);
+            }
+            KeyCode::Char(c) => {
+                prompt.query.push(c);
+                prompt.results.select(None);
+            }
+            _ => (),
+        }
+    }
+
+    // Edit the note of the highlight prompt in $VISUAL or $EDITOR, the terminal is handed over
+    // to the editor until it exits
+    fn open_note_editor<B: Backend>(&mut self, terminal: &mut Terminal<B>) {
+        let Some(prompt) = self.highlight_prompt.as_mut() else {
+            return;
+        };
+
+        // Wait for the input thread to stop polling, so that it does not take the keys
+        self.input_paused.store(true, Ordering::Relaxed);
+        thread::sleep(INPUT_POLL_INTERVAL * 2);
+        App::capture_mouse(false);
+        ratatui::restore();
+
+        let path = env::temp_dir().join(format!("ers-note-{}.md", process::id()));
+        let editor = env::var("VISUAL")
+            .or(env::var("EDITOR"))
+            .unwrap_or("vi".to_string());
+        let mut editor = editor.split_whitespace();
+
+        if fs::write(&path, &prompt.note).is_ok() {
+            let status = Command::new(editor.next().unwrap_or("vi"))
+                .args(editor)
+                .arg(&path)
+                .status();
+
+            if status.is_ok_and(|x| x.success()) {
+                if let Ok(note) = fs::read_to_string(&path) {
+                    prompt.note = note.trim_end().to_string();
                 }
             }
+            let _ = fs::remove_file(&path);
+        }
+
+        enable_raw_mode().unwrap();
+        execute!(io::stdout(), EnterAlternateScreen).unwrap();
+        App::capture_mouse(self.config.mouse);
+        terminal.clear().unwrap();
+        self.input_paused.store(false, Ordering::Relaxed);
+    }
+
+    // Move the reading position so that the position in the text of the page is shown
+    fn show_position(&mut self, position: usize) {
+        let row = self
+            .layouts
+            .get(
+                &mut self.book,
+                &self.selection.as_ref().unwrap().page,
+                self.reading_area,
+                self.picker,
+            )
+            .row(position);
+        let view_height = self.reading_area.height as usize;
+
+        if let Screen::Reading { offset, .. } = &mut self.current_screen {
+            *offset = match self.mode {
+                ReadingMode::Paged => row,
+                _ if row < *offset => row,
+                _ if row >= *offset + view_height => row + 1 - view_height,
+                _ => *offset,
+            };
+        }
+    }
+
+    fn handle_selection_keypress(&mut self, key: KeyEvent) {
+        let action = self.config.keys.action(&key, Context::Selection);
+        let selection = self.selection.as_mut().unwrap();
+        let page = self.book.pages.get(&selection.page).unwrap();
+        let text: Vec<char> = page.text().chars().collect();
+        let last = text.len().saturating_sub(1);
+
+        match (action, key.code) {
+            (_, KeyCode::Esc) => {
+                self.selection = None;
+                return;
+            }
+            (Some(Action::StartSelection), _) => {
+                selection.anchor = match selection.anchor {
+                    Some(_) => None,
+                    None => Some(selection.cursor),
+                };
+            }
+            (Some(Action::NextWord), _) => {
+                // Move to the end of the next word
+                let mut index = (selection.cursor + 1).min(last);
+                while index < last && text[index].is_whitespace() {
+                    index += 1;
+                }
+                while index < last && !text[index + 1].is_whitespace() {
+                    index += 1;
+                }
+                selection.cursor = index;
+            }
+            (Some(Action::PreviousWord), _) => {
+                // Move to the start of the previous word
+                let mut index = selection.cursor.saturating_sub(1);
+                while index > 0 && text[index].is_whitespace() {
+                    index -= 1;
+                }
+                while index > 0 && !text[index - 1].is_whitespace() {
+                    index -= 1;
+                }
+                selection.cursor = index;
+            }
+            (Some(Action::NextLine), _) => {
+                let layout = self.layouts.get(
+                    &mut self.book,
+                    &selection.page,
+                    self.reading_area,
+                    self.picker,
+                );
+                selection.cursor = layout.line_start(selection.cursor, 1);
+            }
+            (Some(Action::PreviousLine), _) => {
+                let layout = self.layouts.get(
+                    &mut self.book,
+                    &selection.page,
+                    self.reading_area,
+                    self.picker,
+                );
+                selection.cursor = layout.line_start(selection.cursor, -1);
+            }
+            (_, KeyCode::Enter) => {
+                let selection = self.selection.take().unwrap();
+
+                self.highlight_prompt = Some(HighlightPrompt {
+                    range: selection.range(),
+                    page: selection.page,
+                    color: HighlightColor::Yellow,
+                    note: String::new(),
+                });
+                return;
+            }
+            (Some(Action::Delete), _) => {
+                let cursor = selection.cursor;
+                self.highlights
+                    .highlights
+                    .retain(|x| x.page != selection.page || !(x.start..x.end).contains(&cursor));
+                let result = self.highlights.save(&self.book_hash);
+                self.report(result, "highlights");
+            }
+            _ => (),
+        }
+
+        let cursor = self.selection.as_ref().unwrap().cursor;
+        self.show_position(cursor);
+    }
+
+    fn handle_highlight_keypress(&mut self, key: KeyEvent) {
+        let prompt = self.highlight_prompt.as_mut().unwrap();
+
+        match key.code {
+            KeyCode::Esc => self.highlight_prompt = None,
+            KeyCode::Enter => {
+                let prompt = self.highlight_prompt.take().unwrap();
+                let text: String = self
+                    .book
+                    .pages
+                    .get(&prompt.page)
+                    .unwrap()
+                    .text()
+                    .chars()
+                    .skip(prompt.range.start)
+                    .take(prompt.range.len())
+                    .collect();
+                let note = prompt.note.trim();
+
+                self.highlights.highlights.push(Highlight {
+                    page: prompt.page,
+                    start: prompt.range.start,
+                    end: prompt.range.end,
+                    color: prompt.color,
+                    note: (!note.is_empty()).then(|| note.to_string()),
+                    text: text.trim().to_string(),
+                });
+                let result = self.highlights.save(&self.book_hash);
+                self.report(result, "highlights");
+            }
+            KeyCode::Tab => prompt.color = prompt.color.next(),
+            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.edit_note = true;
+            }
+            KeyCode::Backspace => {
+                prompt.note.pop();
+            }
+            KeyCode::Char(c) => prompt.note.push(c),
+            _ => (),
+        }
+    }
+
+    fn handle_library_keypress(&mut self, key: KeyEvent) {
+        let action = self.config.keys.action(&key, Context::Library);
+        let Screen::Library {
+            list_state,
+            filter,
+            filtering,
+            sort,
+            prev_screen,
+        } = &mut self.current_screen
+        else {
+            return;
+        };
+
+        if *filtering {
+            match key.code {
+                KeyCode::Enter => *filtering = false,
+                KeyCode::Esc => {
+                    *filtering = false;
+                    filter.clear();
+                }
+                KeyCode::Backspace => {
+                    filter.pop();
+                }
+                KeyCode::Char(c) => filter.push(c),
+                _ => return,
+            }
+            list_state.select(Some(0));
+            return;
+        }
+
+        let entries = self.history.entries(filter, *sort);
+        let selected = list_state
+            .selected()
+            .and_then(|x| entries.get(x))
+            .map(|x| (*x).clone());
+
+        match (action, key.code) {
+            (Some(Action::Quit), _) => self.exit = true,
+            (Some(Action::ReloadConfig), _) => self.reload_config(),
+            (Some(Action::SwitchTheme), _) => self.switch_theme(),
+            (_, KeyCode::Esc) if !filter.is_empty() => {
+                filter.clear();
+                list_state.select(Some(0));
+            }
+            (_, KeyCode::Esc) if prev_screen.is_some() => {
+                let prev_screen = prev_screen.take().unwrap();
+
+                self.current_screen = Screen::Reading {
+                    page: prev_screen.page,
+                    offset: prev_screen.offset,
+                }
+            }
+            (Some(Action::ScrollUp), _) => list_state.select_previous(),
+            (Some(Action::ScrollDown), _) => list_state.select_next(),
+            (Some(Action::Search), _) => *filtering = true,
+            (Some(Action::Sort), _) => {
+                *sort = sort.next();
+                list_state.select(Some(0));
+            }
+            (Some(Action::Delete), _) => {
+                if let Some(entry) = selected {
+                    // The open book stays in the history
+                    if entry.id != self.book_hash {
+                        self.history.remove(&entry.id);
+                        let result = self.history.save();
+                        self.report(result, "history");
+                    }
+                }
+            }
+            (_, KeyCode::Enter) => {
+                let Some(entry) = selected.filter(|x| Path::new(&x.path).exists()) else {
+                    return;
+                };
+
+                if entry.id == self.book_hash {
+                    if let Some(prev_screen) = prev_screen.take() {
+                        self.current_screen = Screen::Reading {
+                            page: prev_screen.page,
+                            offset: prev_screen.offset,
+                        };
+                    }
+                } else {
+                    match Book::new(&entry.path) {
+                        Ok(book) => self.open_book(&entry.path, book),
+                        Err(err) => {
+                            self.message = Some(Err(format!("Could not open the book: {}", err)))
+                        }
+                    }
+                }
+            }
+            _ => (),
         }
     }
 
-    fn handle_keypress(&mut self, code: KeyCode) {
+    // The filter of the table of contents takes all the keys while it is typed, and the best match
+    // is selected as it changes
+    fn handle_toc_filter_keypress(&mut self, code: KeyCode) {
+        let Screen::Info {
+            toc_state,
+            filter,
+            entries,
+            filtering,
+            reading_entry,
+            ..
+        } = &mut self.current_screen
+        else {
+            return;
+        };
+
         match code {
-            KeyCode::Char('q') | KeyCode::Char('Q') => self.exit = true, // Global shortcut
+            KeyCode::Enter => *filtering = false,
+            KeyCode::Esc => {
+                *filtering = false;
+                filter.clear();
+            }
+            KeyCode::Backspace => {
+                filter.pop();
+            }
+            KeyCode::Char(c) => filter.push(c),
+            _ => return,
+        }
+        *entries = self.book.filter_toc(filter);
+        toc_state.select(toc_selection(filter, entries, *reading_entry));
+    }
+
+    // Read the config file again. The image mode is kept, as the images are already encoded for
+    // the terminal.
+    fn reload_config(&mut self) {
+        match Config::load() {
+            Ok(config) => {
+                self.theme = config.theme(&config.theme).unwrap();
+                self.theme_name = config.theme.clone();
+                self.config = Config {
+                    image_mode: self.config.image_mode,
+                    ..config
+                };
+                App::capture_mouse(self.config.mouse);
+                self.dragging = false;
+
+                self.message = Some(Ok("Config reloaded".to_string()));
+
+                // The books that were not changed follow the config
+                let saved = self.load_or_report(Typography::load(&self.book_hash), "typography");
+                if saved.is_none() {
+                    self.apply_typography(Typography::new(&self.config));
+                }
+            }
+            Err(err) => self.message = Some(Err(err)),
+        }
+    }
+
+    // Lay out the pages with the typography, keeping the text at the top of the screen in view
+    fn apply_typography(&mut self, typography: Typography) {
+        if let Screen::Reading { page, offset } = &self.current_screen {
+            if self.pending_position.is_none() {
+                self.pending_position = self
+                    .layouts
+                    .cached(page, self.reading_area)
+                    .map(|layout| layout.position(*offset));
+            }
+        }
+
+        self.typography = typography;
+        self.layouts.set_typography(typography);
+    }
+
+    // Change the typography of the book, which is remembered for the book
+    fn change_typography(&mut self, action: Action) {
+        let mut typography = self.typography;
+        let message = match action {
+            Action::Narrower => {
+                typography.max_width =
+                    Some(self.reading_area.width.saturating_sub(5).max(MIN_WIDTH));
+                format!("Line width: {}", typography.max_width.unwrap())
+            }
+            Action::Wider => {
+                typography.max_width = typography
+                    .max_width
+                    .map(|x| x + 5)
+                    .filter(|x| *x < self.text_width);
+                match typography.max_width {
+                    Some(width) => format!("Line width: {}", width),
+                    None => "Line width: full".to_string(),
+                }
+            }
+            Action::DecreaseMargin => {
+                typography.margin = typography.margin.saturating_sub(1);
+                format!("Margin: {}", typography.margin)
+            }
+            // The margins take a cell from each side
+            Action::IncreaseMargin => {
+                if self.text_width >= MIN_WIDTH + 2 {
+                    typography.margin += 1;
+                }
+                format!("Margin: {}", typography.margin)
+            }
+            Action::ParagraphSpacing => {
+                typography.paragraph_spacing = if typography.paragraph_spacing == 0 {
+                    1
+                } else {
+                    0
+                };
+                format!("Paragraph spacing: {}", typography.paragraph_spacing)
+            }
+            Action::Indent => {
+                typography.indent = match typography.indent {
+                    0 => 2,
+                    1..=2 => 4,
+                    _ => 0,
+                };
+                format!("Indent: {}", typography.indent)
+            }
+            Action::Justify => {
+                typography.justify = !typography.justify;
+                format!("Justify: {}", if typography.justify { "on" } else { "off" })
+            }
+            Action::Hyphenate => {
+                typography.hyphenate = !typography.hyphenate;
+                format!(
+                    "Hyphenation: {}",
+                    if typography.hyphenate { "on" } else { "off" }
+                )
+            }
+            _ => return,
+        };
+
+        self.apply_typography(typography);
+        self.message = Some(Ok(message));
+        let result = self.typography.save(&self.book_hash);
+        self.report(result, "typography");
+    }
+
+    // Switch to the next theme, the theme of the config is used again when it is reloaded
+    fn switch_theme(&mut self) {
+        let names = self.config.theme_names();
+        let index = names.iter().position(|x| *x == self.theme_name);
+        let name = names[index.map(|x| (x + 1) % names.len()).unwrap_or(0)].clone();
+
+        // The themes are checked when the config is loaded
+        self.theme = self.config.theme(&name).unwrap();
+        self.message = Some(Ok(format!("Theme: {}", name)));
+        self.theme_name = name;
+    }
+
+    // The images on the reading screen when it was last rendered from the top, with their
+    // chapters
+    pub fn visible_images(&self) -> Vec<(String, String)> {
+        self.click_targets
+            .iter()
+            .filter_map(|(_, target)| match target {
+                ClickTarget::Image { page, path } => Some((page.clone(), path.clone())),
+                ClickTarget::Link(_) => None,
+            })
+            .collect()
+    }
+
+    // Show the images of the chapter in the viewer, starting at the image
+    fn open_viewer(&mut self, chapter: &str, image: &str) {
+        let Screen::Reading { page, offset } = &self.current_screen else {
+            return;
+        };
+        // The images missing from the book are shown as placeholders, and are not viewed
+        let mut images = self.book.pages.get(chapter).unwrap().images();
+        images.retain(|x| self.book.images.contains_key(x));
+
+        self.current_screen = Screen::Viewer {
+            index: images.iter().position(|x| x == image).unwrap_or(0),
+            images,
+            zoom: Zoom::Fit,
+            pan: (0, 0),
+            prev_screen: ReadingRecord {
+                page: page.clone(),
+                offset: *offset,
+            },
+        }
+    }
+
+    // Scroll up by the rows, continuing into the end of the previous chapter in continuous mode
+    fn scroll_up(&mut self, rows: usize) {
+        let Screen::Reading { page, offset } = &mut self.current_screen else {
+            return;
+        };
+
+        if *offset >= rows || self.mode != ReadingMode::Continuous {
+            *offset = offset.saturating_sub(rows);
+            return;
+        }
+
+        let missing = rows - *offset;
+        let page = page.clone();
+        match self.neighbour_chapter(&page, false) {
+            // The row after the end of the previous chapter is the divider
+            Some(previous) => {
+                self.current_screen = Screen::Reading {
+                    offset: (self.chapter_height(&previous) + 1).saturating_sub(missing),
+                    page: previous,
+                };
+            }
+            None => self.current_screen = Screen::Reading { page, offset: 0 },
+        }
+    }
+
+    // The wheel scrolls the reading screen and the table of contents, and the links, the images,
+    // the entries of the table of contents and the scrollbar can be clicked
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        // The prompts and the selection are only used with the keyboard
+        if self.search_prompt.is_some()
+            || self.bookmark_prompt.is_some()
+            || self.highlight_prompt.is_some()
+            || self.selection.is_some()
+        {
+            return;
+        }
+        // The message of a click is kept when the button is released
+        if !matches!(mouse.kind, MouseEventKind::Up(_)) {
+            self.message = None;
+            self.chosen_image = None;
+        }
+        let position = Position::new(mouse.column, mouse.row);
+
+        match &mut self.current_screen {
+            Screen::Info {
+                toc_state, entries, ..
+            } => match mouse.kind {
+                MouseEventKind::ScrollUp | MouseEventKind::ScrollDown if !entries.is_empty() => {
+                    let selected = match (mouse.kind, toc_state.selected()) {
+                        (MouseEventKind::ScrollUp, Some(selected)) => selected.saturating_sub(1),
+                        (_, Some(selected)) => (selected + 1).min(entries.len() - 1),
+                        (_, None) => 0,
+                    };
+                    toc_state.select(Some(selected));
+                }
+                MouseEventKind::Down(MouseButton::Left) if self.toc_area.contains(position) => {
+                    let index = toc_state.offset() + (position.y - self.toc_area.y) as usize;
+
+                    if let Some((entry, _)) = entries.get(index) {
+                        let (page, position, _) = &self.book.toc[*entry];
+                        self.pending_position = None;
+                        self.go_to(page.clone(), *position, 0);
+                    }
+                }
+                _ => (),
+            },
+            Screen::Reading { offset, .. } => {
+                let scrollbar = self
+                    .scrollbar
+                    .filter(|(area, _)| self.dragging || area.contains(position));
+
+                match mouse.kind {
+                    MouseEventKind::ScrollUp if self.mode == ReadingMode::Paged => {
+                        self.flip_page(false)
+                    }
+                    MouseEventKind::ScrollDown if self.mode == ReadingMode::Paged => {
+                        self.flip_page(true)
+                    }
+                    MouseEventKind::ScrollUp => self.scroll_up(self.config.scroll_step),
+                    MouseEventKind::ScrollDown => *offset += self.config.scroll_step,
+                    // The offset follows the row of the scrollbar that is pointed at
+                    MouseEventKind::Down(MouseButton::Left)
+                    | MouseEventKind::Drag(MouseButton::Left)
+                        if scrollbar.is_some() =>
+                    {
+                        let (area, bottom) = scrollbar.unwrap();
+                        let row = position.y.clamp(area.top(), area.bottom() - 1) - area.y;
+
+                        *offset = bottom * row as usize / (area.height as usize - 1).max(1);
+                        self.dragging = true;
+                    }
+                    MouseEventKind::Down(MouseButton::Left) => {
+                        let target = self
+                            .click_targets
+                            .iter()
+                            .find(|(area, _)| area.contains(position))
+                            .map(|(_, target)| target.clone());
+
+                        match target {
+                            Some(ClickTarget::Link(link)) => match self.book.link_position(&link) {
+                                Some((page, position)) => self.go_to(page, position, 0),
+                                None => {
+                                    self.message =
+                                        Some(Err("The link is outside the book".to_string()))
+                                }
+                            },
+                            Some(ClickTarget::Image { page, path }) => {
+                                self.open_viewer(&page, &path)
+                            }
+                            None => (),
+                        }
+                    }
+                    MouseEventKind::Up(MouseButton::Left) => self.dragging = false,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_keypress(&mut self, key: KeyEvent) {
+        let code = key.code;
+        self.message = None;
+        let chosen_image = self.chosen_image.take();
+
+        // The search prompt takes all the keys while it is open
+        if self.search_prompt.is_some() {
+            self.handle_search_keypress(code);
+            return;
+        }
+        if self.bookmark_prompt.is_some() {
+            self.handle_bookmark_keypress(code);
+            return;
+        }
+        if self.highlight_prompt.is_some() {
+            self.handle_highlight_keypress(key);
+            return;
+        }
+        if self.selection.is_some() {
+            self.handle_selection_keypress(key);
+            return;
+        }
+        // The library takes all the keys, as the filter is typed into it
+        if matches!(self.current_screen, Screen::Library { .. }) {
+            self.handle_library_keypress(key);
+            return;
+        }
+        if matches!(
+            self.current_screen,
+            Screen::Info {
+                filtering: true,
+                ..
+            }
+        ) {
+            self.handle_toc_filter_keypress(code);
+            return;
+        }
+
+        let action = self.config.keys.action(&key, self.current_screen.context());
+        match action {
+            // Global shortcuts
+            Some(Action::Quit) => self.exit = true,
+            Some(Action::ReloadConfig) => self.reload_config(),
+            Some(Action::SwitchTheme) => self.switch_theme(),
             _ => match &mut self.current_screen {
+                Screen::Bookmarks {
+                    list_state,
+                    prev_screen,
+                } => match (action, code) {
+                    (Some(Action::Bookmarks), _) | (_, KeyCode::Esc) => {
+                        self.current_screen = Screen::Reading {
+                            page: prev_screen.page.clone(),
+                            offset: prev_screen.offset,
+                        }
+                    }
+                    (Some(Action::ScrollUp), _) => {
+                        list_state.select_previous();
+                    }
+                    (Some(Action::ScrollDown), _) => {
+                        list_state.select_next();
+                    }
+                    (_, KeyCode::Enter) => {
+                        if let Some(bookmark) = list_state
+                            .selected()
+                            .and_then(|x| self.bookmarks.bookmarks.get(x))
+                        {
+                            self.go_to(bookmark.page.clone(), bookmark.position, 0);
+                        }
+                    }
+                    (Some(Action::Delete), _) => {
+                        if let Some(index) = list_state
+                            .selected()
+                            .filter(|x| *x < self.bookmarks.bookmarks.len())
+                        {
+                            self.bookmarks.bookmarks.remove(index);
+                            let result = self.bookmarks.save(&self.book_hash);
+                            self.report(result, "bookmarks");
+                        }
+                    }
+                    _ => (),
+                },
                 Screen::Info {
                     toc_state,
+                    filter,
+                    entries,
+                    filtering,
+                    reading_entry,
                     prev_screen,
-                    ..
-                } => match code {
-                    KeyCode::Enter => {
-                        self.current_screen = Screen::Reading {
-                            page: if toc_state.selected().is_none() {
-                                self.book.order.first().unwrap().clone()
+                } => {
+                    let last = entries.len().saturating_sub(1);
+                    // A page moves by the entries that fit the screen
+                    let page = (self.toc_area.height as usize).max(1);
+
+                    match (action, code) {
+                        (_, KeyCode::Enter) => {
+                            let target = match toc_state.selected() {
+                                Some(selected) => entries.get(selected).map(|x| {
+                                    let (page, position, _) = &self.book.toc[x.0];
+                                    (page.clone(), *position)
+                                }),
+                                // The book is started from the beginning if no entry is selected
+                                None if filter.is_empty() => {
+                                    self.book.order.first().map(|x| (x.clone(), 0))
+                                }
+                                None => None,
+                            };
+
+                            if let Some((page, position)) = target {
+                                self.pending_position = None;
+                                self.go_to(page, position, 0);
+                            }
+                        }
+                        (_, KeyCode::Esc) if !filter.is_empty() => {
+                            filter.clear();
+                            *entries = self.book.filter_toc(filter);
+                            toc_state.select(toc_selection(filter, entries, *reading_entry));
+                        }
+                        (Some(Action::Search), _) => *filtering = true,
+                        (Some(Action::ScrollUp), _) => {
+                            if entries.is_empty() {
+                                return;
+                            }
+
+                            if toc_state.selected().is_none() {
+                                toc_state.select(Some(last));
+                            } else if toc_state.selected().unwrap() > 0 {
+                                toc_state.select(Some(toc_state.selected().unwrap() - 1));
                             } else {
-                                self.book
-                                    .toc
-                                    .get(toc_state.selected().unwrap())
-                                    .unwrap()
-                                    .0
-                                    .clone()
-                            },
-                            offset: 0,
-                        };
-                    }
-                    KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') => {
-                        if self.book.toc.is_empty() {
-                            return;
+                                toc_state.select(None);
+                            }
                         }
+                        (Some(Action::ScrollDown), _) => {
+                            if entries.is_empty() {
+                                return;
+                            }
 
-                        if toc_state.selected().is_none() {
-                            toc_state.select(Some(self.book.toc.len() - 1));
-                        } else if toc_state.selected().unwrap() > 0 {
-                            toc_state.select(Some(toc_state.selected().unwrap() - 1));
-                        } else {
-                            toc_state.select(None);
+                            if toc_state.selected().is_none() {
+                                toc_state.select(Some(0));
+                            } else if toc_state.selected().unwrap() < last {
+                                toc_state.select(Some(toc_state.selected().unwrap() + 1));
+                            } else {
+                                toc_state.select(None);
+                            }
                         }
-                    }
-                    KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') => {
-                        if self.book.toc.is_empty() {
-                            return;
+                        (Some(Action::PageUp), _) if !entries.is_empty() => {
+                            toc_state.select(Some(
+                                toc_state.selected().unwrap_or(0).saturating_sub(page),
+                            ));
+                        }
+                        (Some(Action::PageDown), _) if !entries.is_empty() => {
+                            let selected = toc_state.selected().map_or(0, |x| x + page);
+                            toc_state.select(Some(selected.min(last)));
                         }
+                        (_, KeyCode::Home) if !entries.is_empty() => toc_state.select(Some(0)),
+                        (_, KeyCode::End) if !entries.is_empty() => toc_state.select(Some(last)),
+                        (Some(Action::BookInfo), _) | (_, KeyCode::Esc)
+                            if prev_screen.is_some() =>
+                        {
+                            let prev_screen = prev_screen.as_ref().unwrap();
 
-                        if toc_state.selected().is_none() {
-                            toc_state.select(Some(0));
-                        } else if toc_state.selected().unwrap() < (self.book.toc.len() - 1) {
-                            toc_state.select(Some(toc_state.selected().unwrap() + 1));
-                        } else {
-                            toc_state.select(None);
+                            self.current_screen = Screen::Reading {
+                                page: prev_screen.page.clone(),
+                                offset: prev_screen.offset,
+                            }
+                        }
+                        (Some(Action::Library), _) => {
+                            let prev_screen = prev_screen.clone();
+                            self.open_library(prev_screen);
                         }
+                        _ => (),
                     }
-                    KeyCode::Esc => {
-                        if prev_screen.is_some() {
-                            let prev_screen = prev_screen.as_ref().unwrap();
+                }
+                Screen::Viewer {
+                    images,
+                    index,
+                    zoom,
+                    pan,
+                    prev_screen,
+                } => {
+                    // Pan by a few cells at a time
+                    let (step_x, step_y) = self
+                        .picker
+                        .map(|p| (p.font_size().0 as u32 * 4, p.font_size().1 as u32 * 2))
+                        .unwrap_or_default();
 
+                    match (action, code) {
+                        (Some(Action::ViewImage), _) | (_, KeyCode::Esc) => {
                             self.current_screen = Screen::Reading {
                                 page: prev_screen.page.clone(),
                                 offset: prev_screen.offset,
                             }
                         }
+                        (Some(Action::Zoom), _) => {
+                            *zoom = match zoom {
+                                Zoom::Fit => Zoom::Actual,
+                                Zoom::Actual => Zoom::Fit,
+                            };
+                            *pan = (0, 0);
+                        }
+                        (Some(Action::NextImage), _) if *index < images.len() - 1 => {
+                            *index += 1;
+                            *pan = (0, 0);
+                        }
+                        (Some(Action::PreviousImage), _) if *index > 0 => {
+                            *index -= 1;
+                            *pan = (0, 0);
+                        }
+                        // The pan is clamped to the size of the image when rendering
+                        (Some(Action::PanLeft), _) => pan.0 = pan.0.saturating_sub(step_x),
+                        (Some(Action::PanRight), _) => pan.0 = pan.0.saturating_add(step_x),
+                        (Some(Action::PanUp), _) => pan.1 = pan.1.saturating_sub(step_y),
+                        (Some(Action::PanDown), _) => pan.1 = pan.1.saturating_add(step_y),
+                        _ => (),
                     }
-                    _ => (),
-                },
-                Screen::Reading { page, offset } => match code {
-                    KeyCode::Char('i') | KeyCode::Char('I') => {
-                        self.current_screen = Screen::Info {
-                            toc_state: ListState::default(),
-                            prev_screen: Some(ReadingRecord {
-                                page: page.clone(),
-                                offset: offset.clone(),
-                            }),
+                }
+                // Handled by handle_library_keypress
+                Screen::Library { .. } => (),
+                Screen::Reading { page, offset } => match action {
+                    Some(Action::ViewImage) => {
+                        let image = chosen_image
+                            .and_then(|x| self.visible_images().get(x).cloned())
+                            .or(self.nearest_image.clone());
+
+                        if let Some((chapter, image)) = image {
+                            self.open_viewer(&chapter, &image);
                         }
                     }
-                    KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') => {
-                        if *offset >= 1 {
-                            *offset -= 1;
+                    Some(Action::ChooseImage) => {
+                        let count = self.visible_images().len();
+                        if count > 0 {
+                            self.chosen_image = Some(chosen_image.map_or(0, |x| (x + 1) % count));
                         }
                     }
-                    KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') => {
-                        *offset += 1;
+                    Some(Action::BookInfo) => {
+                        let position = self
+                            .layouts
+                            .get(&mut self.book, page, self.reading_area, self.picker)
+                            .position(*offset);
+                        self.current_screen = Screen::info(
+                            &self.book,
+                            Some(ReadingRecord {
+                                page: page.clone(),
+                                offset: *offset,
+                            }),
+                            position,
+                        )
                     }
-                    KeyCode::Left | KeyCode::Char('h') | KeyCode::Char('H') => {
-                        let current_index = self.book.order.iter().position(|x| x == page).unwrap();
+                    Some(Action::Select) => {
+                        let page = page.clone();
+                        let cursor = self
+                            .layouts
+                            .get(&mut self.book, &page, self.reading_area, self.picker)
+                            .position(*offset);
 
-                        if current_index > 0 {
-                            self.current_screen = Screen::Reading {
-                                page: self.book.order.get(current_index - 1).unwrap().to_string(),
-                                offset: 0,
-                            };
-                        }
+                        self.selection = Some(Selection {
+                            page,
+                            cursor,
+                            anchor: None,
+                        });
+                    }
+                    Some(Action::AddBookmark) => {
+                        self.bookmark_prompt = Some(String::new());
                     }
-                    KeyCode::Right | KeyCode::Char('l') | KeyCode::Char('L') => {
-                        let current_index = self.book.order.iter().position(|x| x == page).unwrap();
+                    Some(Action::Bookmarks) => {
+                        let mut list_state = ListState::default();
+                        if !self.bookmarks.bookmarks.is_empty() {
+                            list_state.select(Some(0));
+                        }
 
-                        if current_index < self.book.order.len() - 1 {
-                            self.current_screen = Screen::Reading {
-                                page: self.book.order.get(current_index + 1).unwrap().to_string(),
-                                offset: 0,
-                            };
+                        self.current_screen = Screen::Bookmarks {
+                            list_state,
+                            prev_screen: ReadingRecord {
+                                page: page.clone(),
+                                offset: *offset,
+                            },
                         }
                     }
+                    Some(Action::Search) => {
+                        self.search_prompt = Some(SearchPrompt::default());
+                    }
+                    Some(Action::NextMatch) if !self.search_hits.is_empty() => {
+                        let index = self.current_hit.map(|x| x + 1).unwrap_or(0);
+                        self.go_to_hit(index % self.search_hits.len());
+                    }
+                    Some(Action::PreviousMatch) if !self.search_hits.is_empty() => {
+                        let index = self.current_hit.unwrap_or(0);
+                        let count = self.search_hits.len();
+                        self.go_to_hit((index + count - 1) % count);
+                    }
+                    Some(Action::ClearSearch) => {
+                        self.search_hits.clear();
+                        self.current_hit = None;
+                    }
+                    Some(Action::SwitchMode) => {
+                        self.mode = match self.mode {
+                            ReadingMode::Scroll => ReadingMode::Paged,
+                            ReadingMode::Paged => ReadingMode::Continuous,
+                            ReadingMode::Continuous => ReadingMode::Scroll,
+                        };
+                    }
+                    Some(Action::ScrollUp | Action::PageUp) if self.mode == ReadingMode::Paged => {
+                        self.flip_page(false)
+                    }
+                    Some(Action::ScrollDown | Action::PageDown)
+                        if self.mode == ReadingMode::Paged =>
+                    {
+                        self.flip_page(true)
+                    }
+                    Some(Action::ScrollUp) => self.scroll_up(self.config.scroll_step),
+                    Some(Action::ScrollDown) => *offset += self.config.scroll_step,
+                    // Scroll by a screen, keeping the last line in view
+                    Some(Action::PageUp) => {
+                        self.scroll_up((self.reading_area.height as usize).saturating_sub(1).max(1))
+                    }
+                    Some(Action::PageDown) => {
+                        *offset += (self.reading_area.height as usize).saturating_sub(1).max(1)
+                    }
+                    // The chapter keys are mirrored in right-to-left books, so that the left key
+                    // turns to the next chapter
+                    Some(Action::PreviousChapter) => {
+                        self.turn_chapter(self.book.rtl, 0);
+                    }
+                    Some(Action::NextChapter) => {
+                        self.turn_chapter(!self.book.rtl, 0);
+                    }
+                    Some(
+                        action @ (Action::Narrower
+                        | Action::Wider
+                        | Action::DecreaseMargin
+                        | Action::IncreaseMargin
+                        | Action::ParagraphSpacing
+                        | Action::Indent
+                        | Action::Justify
+                        | Action::Hyphenate),
+                    ) => self.change_typography(action),
                     _ => (),
                 },
             },
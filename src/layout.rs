@@ -0,0 +1,958 @@
+use std::{collections::HashMap, ops::Range};
+
+use ratatui::layout::Rect;
+use ratatui_image::picker::Picker;
+use unicode_bidi::{Level, ParagraphBidiInfo};
+use unicode_bidi_mirroring::get_mirrored;
+use unicode_linebreak::{break_property, linebreaks, BreakClass};
+use unicode_width::UnicodeWidthChar;
+
+use crate::models::{
+    book::Book,
+    hyphenator::Hyphenator,
+    image::Image,
+    page::{ContentType, Direction, Page},
+    typography::Typography,
+};
+
+const SOFT_HYPHEN: char = '\u{ad}';
+
+// A run of text of an element on a line, which keeps where it came from in the page. The style
+// of the text is given by the element, see Theme::content_style.
+#[derive(Clone)]
+pub struct Fragment {
+    pub text: String,
+    // The number of blank cells before the text, for the indentation and the justification
+    pub gap: usize,
+    // The index of the element in Page::content
+    pub content: usize,
+    // The offset in characters of the text in the element
+    pub offset: usize,
+    // The position in characters in the text of the page, see Page::text
+    pub position: usize,
+}
+
+pub enum Item {
+    Line(Vec<Fragment>),
+    // The size is in cells
+    Image {
+        path: String,
+        width: u16,
+        height: u16,
+        // The position of the image in the text of the page
+        position: usize,
+    },
+}
+
+impl Item {
+    pub fn height(&self) -> usize {
+        match self {
+            Item::Line(_) => 1,
+            Item::Image { height, .. } => *height as usize,
+        }
+    }
+}
+
+// A character of a paragraph, with the element it came from
+#[derive(Clone)]
+struct Glyph {
+    ch: char,
+    width: usize,
+    content: usize,
+    offset: usize,
+    position: usize,
+}
+
+// A page laid out into lines and images for an area
+pub struct Layout {
+    area: (u16, u16),
+    typography: Typography,
+    // The language of the book, for the text without a language
+    language: Option<String>,
+    items: Vec<Item>,
+    // The row that each item starts at
+    tops: Vec<usize>,
+    // The paragraph that each item belongs to, an image is a paragraph on its own
+    paragraphs: Vec<usize>,
+    paragraph_count: usize,
+    height: usize,
+    // The rows that the pages start at in paged mode
+    pages: Vec<usize>,
+}
+
+impl Layout {
+    pub fn new(
+        page: &Page,
+        images: &mut HashMap<String, Image>,
+        picker: Option<Picker>,
+        area: Rect,
+        typography: Typography,
+        hyphenator: &mut Hyphenator,
+        language: Option<&str>,
+    ) -> Layout {
+        let mut layout = Layout {
+            area: (area.width, area.height),
+            typography,
+            language: language.map(|x| x.to_string()),
+            items: vec![],
+            tops: vec![],
+            paragraphs: vec![],
+            paragraph_count: 0,
+            height: 0,
+            pages: vec![],
+        };
+        let mut paragraph = vec![];
+        let mut position = 0;
+
+        for (index, content) in page.content.iter().enumerate() {
+            match content {
+                ContentType::Text { text, .. } => {
+                    paragraph.extend(glyphs(text, index, position));
+                    position += text.chars().count();
+                    continue;
+                }
+                ContentType::LineBreak => {
+                    layout.push_paragraph(&paragraph, &page.content, hyphenator);
+                    paragraph.clear();
+                }
+                ContentType::ListItem { number } => {
+                    if !paragraph.is_empty() {
+                        layout.push_paragraph(&paragraph, &page.content, hyphenator);
+                        paragraph.clear();
+                    }
+
+                    // The marker is a single character in the text of the page
+                    let marker = match number {
+                        Some(number) => format!("{}. ", number),
+                        None => "• ".to_string(),
+                    };
+                    paragraph.extend(
+                        glyphs(&marker, index, position)
+                            .into_iter()
+                            .map(|x| Glyph { position, ..x }),
+                    );
+                }
+                // The images that cannot be shown, or that are missing from the book, are described
+                // by their placeholder
+                ContentType::Image { path, alt } | ContentType::Img { path, alt }
+                    if picker.is_none() || !images.contains_key(path) =>
+                {
+                    if !paragraph.is_empty() {
+                        layout.push_paragraph(&paragraph, &page.content, hyphenator);
+                        paragraph.clear();
+                    }
+
+                    let placeholder = glyphs(&image_placeholder(alt), index, position);
+                    layout.push_paragraph(&placeholder, &page.content, hyphenator);
+                }
+                ContentType::Image { path, .. } | ContentType::Img { path, .. } => {
+                    if !paragraph.is_empty() {
+                        layout.push_paragraph(&paragraph, &page.content, hyphenator);
+                        paragraph.clear();
+                    }
+
+                    let native = images.get_mut(path).unwrap().dimensions();
+                    let (width, height) = image_size(native, picker.unwrap().font_size(), area);
+                    layout.space();
+                    layout.push(Item::Image {
+                        path: path.clone(),
+                        width,
+                        height,
+                        position,
+                    });
+                    layout.paragraph_count += 1;
+                }
+                // The anchors are taken out of the content when the page is parsed
+                ContentType::Anchor(_) => continue,
+            }
+
+            // Line breaks, list items and images are a single character in the text of the page
+            position += 1;
+        }
+
+        if !paragraph.is_empty() {
+            layout.push_paragraph(&paragraph, &page.content, hyphenator);
+        }
+
+        layout.paginate();
+
+        layout
+    }
+
+    fn push(&mut self, item: Item) {
+        self.tops.push(self.height);
+        self.paragraphs.push(self.paragraph_count);
+        self.height += item.height();
+        self.items.push(item);
+    }
+
+    // The blank lines before a paragraph, if it does not follow a blank line
+    fn space(&mut self) {
+        let blank = matches!(self.items.last(), Some(Item::Line(x)) if x.is_empty());
+        if self.items.is_empty() || blank || self.typography.paragraph_spacing == 0 {
+            return;
+        }
+
+        for _ in 0..self.typography.paragraph_spacing {
+            self.push(Item::Line(vec![]));
+        }
+        self.paragraph_count += 1;
+    }
+
+    fn push_paragraph(
+        &mut self,
+        glyphs: &[Glyph],
+        content: &[ContentType],
+        hyphenator: &mut Hyphenator,
+    ) {
+        let width = self.area.0 as usize;
+
+        // Only the body text is indented and justified, not the headings, the lists and the
+        // image placeholders
+        let text = glyphs.first().is_some_and(|x| {
+            matches!(
+                content[x.content],
+                ContentType::Text { style, .. } if style.heading.is_none()
+            )
+        });
+        let indent = if text {
+            self.typography.indent.min(width / 2)
+        } else {
+            0
+        };
+
+        if !glyphs.is_empty() {
+            self.space();
+        }
+
+        let hyphens = if text && self.typography.hyphenate {
+            hyphens(glyphs, content, hyphenator, self.language.as_deref())
+        } else {
+            vec![false; glyphs.len()]
+        };
+
+        let direction = self.direction(glyphs.first().map(|x| &content[x.content]));
+        let paragraph: String = glyphs.iter().map(|x| x.ch).collect();
+        let level = match direction {
+            Direction::Ltr => Level::ltr(),
+            Direction::Rtl => Level::rtl(),
+        };
+        let bidi = ParagraphBidiInfo::new(&paragraph, Some(level));
+
+        let lines = wrap(glyphs, width, indent, &hyphens);
+        for (index, (range, hyphen)) in lines.iter().enumerate() {
+            let line = visual_order(glyphs, range.clone(), &bidi);
+            let mut gaps = vec![0; line.len()];
+
+            let indent = if index == 0 { indent } else { 0 };
+            let hyphen_width = usize::from(*hyphen);
+            // The last line of a paragraph is not justified
+            if text && self.typography.justify && index + 1 < lines.len() {
+                justify(&line, width - indent - hyphen_width, &mut gaps);
+            }
+
+            let mut fragments = fragments(&line, &gaps);
+            // The hyphen is not part of the text of the page, it is placed after the last
+            // character of the line, which is on the left in right-to-left paragraphs
+            if let (true, Some(last)) = (*hyphen, glyphs[range.clone()].last()) {
+                let hyphen = Fragment {
+                    text: "-".to_string(),
+                    gap: 0,
+                    content: last.content,
+                    offset: last.offset + 1,
+                    position: last.position + 1,
+                };
+                match direction {
+                    Direction::Ltr => fragments.push(hyphen),
+                    Direction::Rtl => fragments.insert(0, hyphen),
+                }
+            }
+
+            // Right-to-left paragraphs are aligned and indented on the right
+            let used = line.iter().map(|x| x.width).sum::<usize>()
+                + gaps.iter().sum::<usize>()
+                + hyphen_width;
+            if let Some(first) = fragments.first_mut() {
+                first.gap += match direction {
+                    Direction::Ltr => indent,
+                    Direction::Rtl => width.saturating_sub(used + indent),
+                };
+            }
+            self.push(Item::Line(fragments));
+        }
+        self.paragraph_count += 1;
+    }
+
+    // The direction of a paragraph starting with the element, from its dir attribute or its
+    // language, or the language of the book
+    fn direction(&self, content: Option<&ContentType>) -> Direction {
+        let (dir, lang) = match content {
+            Some(ContentType::Text { dir, lang, .. }) => (*dir, lang.as_deref()),
+            _ => (None, None),
+        };
+
+        dir.or_else(|| {
+            lang.or(self.language.as_deref())
+                .map(Direction::of_language)
+        })
+        .unwrap_or(Direction::Ltr)
+    }
+
+    // Split the items into pages that fit into the area. A page never ends in the middle of an
+    // image, or with the first line of a paragraph, or so that only the last line of a paragraph
+    // is left for the next page.
+    fn paginate(&mut self) {
+        let page_height = (self.area.1 as usize).max(1);
+        let mut start = 0;
+
+        while start < self.height {
+            self.pages.push(start);
+
+            let mut end = start + page_height;
+            if end >= self.height {
+                break;
+            }
+
+            // The item that the next page would start with
+            let mut next = self.tops.partition_point(|top| *top < end);
+            if self.tops.get(next) != Some(&end) {
+                // The item crossing the end is an image, so it is moved to the next page
+                next -= 1;
+            } else {
+                let same_paragraph = |a: usize, b: usize| self.paragraphs[a] == self.paragraphs[b];
+
+                // Leave at least two lines of the paragraph for the next page
+                if same_paragraph(next - 1, next)
+                    && (next + 1 == self.items.len() || !same_paragraph(next, next + 1))
+                {
+                    next -= 1;
+                }
+                // Do not end the page with the first line of a paragraph
+                if next > 0
+                    && same_paragraph(next - 1, next)
+                    && (next == 1 || !same_paragraph(next - 2, next - 1))
+                {
+                    next -= 1;
+                }
+            }
+
+            // Pages which cannot be split nicely are filled up
+            if self.tops[next] > start {
+                end = self.tops[next];
+            }
+
+            start = end;
+        }
+
+        if self.pages.is_empty() {
+            self.pages.push(0);
+        }
+    }
+
+    // The total number of rows
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // The rows that the pages start at
+    pub fn pages(&self) -> &[usize] {
+        &self.pages
+    }
+
+    // The lines that have text, with the row and the position in the text of the page that they
+    // start at. The fragments of a line are in the order that they are shown, so the start is
+    // not always the first fragment.
+    fn lines(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.items().filter_map(|(top, item)| match item {
+            Item::Line(fragments) => line_start(fragments).map(|x| (top, x)),
+            Item::Image { .. } => None,
+        })
+    }
+
+    // The items with the row and the position in the text of the page that they start at
+    fn starts(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.items().filter_map(|(top, item)| match item {
+            Item::Line(fragments) => line_start(fragments).map(|x| (top, x)),
+            Item::Image { position, .. } => Some((top, *position)),
+        })
+    }
+
+    // The row of the item showing the character at the position in the text of the page
+    pub fn row(&self, position: usize) -> usize {
+        self.starts()
+            .take_while(|(_, x)| *x <= position)
+            .last()
+            .map(|(top, _)| top)
+            .unwrap_or(0)
+    }
+
+    // The position in the text of the page of the item showing the row
+    pub fn position(&self, row: usize) -> usize {
+        self.starts()
+            .take_while(|(top, _)| *top <= row)
+            .last()
+            .map(|(_, x)| x)
+            .unwrap_or(0)
+    }
+
+    // The start of the line that is the number of lines away from the line showing the position
+    pub fn line_start(&self, position: usize, lines: isize) -> usize {
+        let starts: Vec<usize> = self.lines().map(|(_, x)| x).collect();
+        let index = starts.partition_point(|x| *x <= position).saturating_sub(1);
+        let index = index
+            .saturating_add_signed(lines)
+            .min(starts.len().saturating_sub(1));
+
+        starts.get(index).copied().unwrap_or(0)
+    }
+
+    // All the items with the row that they start at
+    pub fn items(&self) -> impl Iterator<Item = (usize, &Item)> {
+        self.tops.iter().copied().zip(self.items.iter())
+    }
+
+    // The items that are at least partially visible between the rows
+    pub fn visible_items(&self, rows: Range<usize>) -> impl Iterator<Item = (usize, &Item)> {
+        let first = self
+            .tops
+            .partition_point(|top| *top <= rows.start)
+            .saturating_sub(1);
+
+        self.items()
+            .skip(first)
+            .take_while(move |(top, _)| *top < rows.end)
+    }
+}
+
+// Layouts are cached, as laying out a page is too slow to be done on every frame
+pub struct LayoutCache {
+    layouts: HashMap<String, Layout>,
+    typography: Typography,
+    hyphenator: Hyphenator,
+}
+
+impl LayoutCache {
+    pub fn new(typography: Typography) -> Self {
+        LayoutCache {
+            layouts: HashMap::new(),
+            typography,
+            hyphenator: Hyphenator::default(),
+        }
+    }
+
+    // The pages are laid out again with the typography
+    pub fn set_typography(&mut self, typography: Typography) {
+        self.typography = typography;
+        self.layouts.clear();
+    }
+
+    pub fn get(
+        &mut self,
+        book: &mut Book,
+        page: &str,
+        area: Rect,
+        picker: Option<Picker>,
+    ) -> &Layout {
+        let valid = self
+            .layouts
+            .get(page)
+            .is_some_and(|x| x.area == (area.width, area.height));
+
+        if !valid {
+            let layout = Layout::new(
+                book.pages.get(page).unwrap(),
+                &mut book.images,
+                picker,
+                area,
+                self.typography,
+                &mut self.hyphenator,
+                book.language.as_deref(),
+            );
+            self.layouts.insert(page.to_string(), layout);
+        }
+
+        self.layouts.get(page).unwrap()
+    }
+
+    // The layout of the page if it is cached for the area
+    pub fn cached(&self, page: &str, area: Rect) -> Option<&Layout> {
+        self.layouts
+            .get(page)
+            .filter(|x| x.area == (area.width, area.height))
+    }
+}
+
+fn glyphs(text: &str, content: usize, position: usize) -> Vec<Glyph> {
+    text.chars()
+        .enumerate()
+        .map(|(offset, ch)| Glyph {
+            ch,
+            // Soft hyphens are only shown when a line is broken at them
+            width: if ch == SOFT_HYPHEN {
+                0
+            } else {
+                ch.width().unwrap_or(0)
+            },
+            content,
+            offset,
+            position: position + offset,
+        })
+        .collect()
+}
+
+// The glyphs that a line can be broken before, by the line breaking rules of Unicode (UAX #14).
+// Lines are broken after spaces and between CJK characters, but like kinsoku shori, a line does
+// not start with a closing bracket, a small kana, "ー", "。" or "、", or end with an opening
+// bracket.
+fn break_opportunities(glyphs: &[Glyph]) -> Vec<bool> {
+    let text: String = glyphs.iter().map(|x| x.ch).collect();
+    // The byte offset of each glyph in the text
+    let offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+
+    let mut breaks = vec![false; glyphs.len()];
+    for (offset, _) in linebreaks(&text) {
+        // The break at the end of the text is not before a glyph
+        if let Ok(index) = offsets.binary_search(&offset) {
+            breaks[index] = true;
+        }
+    }
+
+    breaks
+}
+
+fn skip_whitespace(glyphs: &[Glyph], mut index: usize) -> usize {
+    while index < glyphs.len() && glyphs[index].ch.is_whitespace() {
+        index += 1;
+    }
+
+    index
+}
+
+fn trim_end(glyphs: &[Glyph], start: usize, mut end: usize) -> usize {
+    while end > start && glyphs[end - 1].ch.is_whitespace() {
+        end -= 1;
+    }
+
+    end
+}
+
+// The glyphs that a line can be broken before with a hyphen, found with the patterns of the
+// language of the words. The words with soft hyphens are only broken at them.
+fn hyphens(
+    glyphs: &[Glyph],
+    content: &[ContentType],
+    hyphenator: &mut Hyphenator,
+    language: Option<&str>,
+) -> Vec<bool> {
+    let mut hyphens = vec![false; glyphs.len()];
+    let letter = |x: &Glyph| x.ch.is_alphabetic() || x.ch == SOFT_HYPHEN;
+
+    let mut start = 0;
+    while start < glyphs.len() {
+        if !letter(&glyphs[start]) {
+            start += 1;
+            continue;
+        }
+        let end = (start..glyphs.len())
+            .find(|x| !letter(&glyphs[*x]))
+            .unwrap_or(glyphs.len());
+
+        let word: String = glyphs[start..end].iter().map(|x| x.ch).collect();
+        let lang = match &content[glyphs[start].content] {
+            ContentType::Text { lang, .. } => lang.as_deref().or(language),
+            _ => None,
+        };
+        if let Some(lang) = lang.filter(|_| !word.contains(SOFT_HYPHEN)) {
+            for index in hyphenator.breaks(&word, lang) {
+                hyphens[start + index] = true;
+            }
+        }
+
+        start = end;
+    }
+
+    hyphens
+}
+
+// Break the glyphs into lines that fit into the width at the break opportunities, words that are
+// too long to fit are split. Words can also be broken at soft hyphens and before the glyphs in
+// hyphens, and the lines that end with a broken word are marked to be shown with a hyphen. The
+// whitespace at the start and the end of the lines is removed. The first line is indented by the
+// number of cells.
+fn wrap(
+    glyphs: &[Glyph],
+    width: usize,
+    indent: usize,
+    hyphens: &[bool],
+) -> Vec<(Range<usize>, bool)> {
+    let mut lines = vec![];
+    let breaks = break_opportunities(glyphs);
+
+    let mut start = skip_whitespace(glyphs, 0);
+    let mut index = start;
+    let mut line_width = indent;
+    // The last position on the current line where the line can be broken, and whether a hyphen
+    // is needed there
+    let mut last_break = None;
+
+    while index < glyphs.len() {
+        if index > start {
+            let soft_hyphen = glyphs[index - 1].ch == SOFT_HYPHEN;
+            if breaks[index] && !soft_hyphen {
+                last_break = Some((index, false));
+            } else if (hyphens[index] || breaks[index] && soft_hyphen) && line_width < width {
+                last_break = Some((index, true));
+            }
+        }
+
+        let glyph = &glyphs[index];
+        if line_width + glyph.width > width && !glyph.ch.is_whitespace() && index > start {
+            let (end, hyphen) = last_break.unwrap_or((forced_end(glyphs, start, index), false));
+            lines.push((start..trim_end(glyphs, start, end), hyphen));
+
+            start = skip_whitespace(glyphs, end);
+            index = start;
+            line_width = 0;
+            last_break = None;
+            continue;
+        }
+
+        line_width += glyph.width;
+        index += 1;
+    }
+
+    if start < glyphs.len() || lines.is_empty() {
+        lines.push((start..trim_end(glyphs, start, glyphs.len()), false));
+    }
+
+    lines
+}
+
+// Where a line without a break opportunity is split, which is before the glyph that does not fit
+// unless that breaks the kinsoku rules of break_opportunities. The split is moved back on the line
+// then, or past the glyphs that cannot start a line if there is no other split, which are left
+// hanging over the width.
+fn forced_end(glyphs: &[Glyph], start: usize, index: usize) -> usize {
+    let allowed = |index: usize| {
+        let starts = !matches!(
+            break_property(glyphs[index].ch as u32),
+            BreakClass::ClosePunctuation
+                | BreakClass::CloseParenthesis
+                | BreakClass::Exclamation
+                | BreakClass::InfixSeparator
+                | BreakClass::NonStarter
+                | BreakClass::ConditionalJapaneseStarter
+                | BreakClass::CombiningMark
+        );
+        let ends = break_property(glyphs[index - 1].ch as u32) != BreakClass::OpenPunctuation;
+
+        starts && ends
+    };
+
+    (start + 1..=index)
+        .rev()
+        .find(|x| allowed(*x))
+        .or_else(|| (index + 1..glyphs.len()).find(|x| allowed(*x)))
+        .unwrap_or(glyphs.len())
+}
+
+// The glyphs of the line in the order that they are shown, by the Unicode Bidirectional
+// Algorithm. The runs of right-to-left text are reversed, and their characters like the brackets
+// are mirrored.
+fn visual_order(glyphs: &[Glyph], line: Range<usize>, bidi: &ParagraphBidiInfo) -> Vec<Glyph> {
+    if !bidi.has_rtl() {
+        return glyphs[line].to_vec();
+    }
+
+    // The byte offset of each glyph in the text of the paragraph, and of the end of the text
+    let mut offsets: Vec<usize> = bidi.text.char_indices().map(|(x, _)| x).collect();
+    offsets.push(bidi.text.len());
+    let glyph = |offset: usize| offsets.binary_search(&offset).unwrap();
+
+    let (levels, runs) = bidi.visual_runs(offsets[line.start]..offsets[line.end]);
+    let mut visual = vec![];
+    for run in runs {
+        let run_glyphs = &glyphs[glyph(run.start)..glyph(run.end)];
+
+        if levels[run.start].is_rtl() {
+            visual.extend(run_glyphs.iter().rev().map(|x| Glyph {
+                ch: get_mirrored(x.ch).unwrap_or(x.ch),
+                ..x.clone()
+            }));
+        } else {
+            visual.extend(run_glyphs.iter().cloned());
+        }
+    }
+
+    visual
+}
+
+// The position in the text of the page of the first character of a line
+fn line_start(fragments: &[Fragment]) -> Option<usize> {
+    fragments.iter().map(|x| x.position).min()
+}
+
+// Spread the cells left on the line over its spaces, by adding to the gaps before the glyphs
+// that follow the spaces
+fn justify(line: &[Glyph], width: usize, gaps: &mut [usize]) {
+    let used: usize = line.iter().map(|x| x.width).sum();
+    let spaces: Vec<usize> = (0..line.len()).filter(|x| line[*x].ch == ' ').collect();
+    if spaces.is_empty() || used >= width {
+        return;
+    }
+
+    // The lines are trimmed, so a space is always followed by a glyph
+    let extra = width - used;
+    for (index, space) in spaces.iter().enumerate() {
+        gaps[space + 1] += extra / spaces.len() + usize::from(index < extra % spaces.len());
+    }
+}
+
+// Merge the glyphs into fragments of continuous text of the same element, a glyph with a gap
+// before it starts a new fragment. The soft hyphens are left out.
+fn fragments(glyphs: &[Glyph], gaps: &[usize]) -> Vec<Fragment> {
+    let mut fragments: Vec<Fragment> = vec![];
+
+    for (glyph, gap) in glyphs.iter().zip(gaps) {
+        if glyph.ch == SOFT_HYPHEN {
+            continue;
+        }
+
+        match fragments.last_mut() {
+            Some(last)
+                if *gap == 0
+                    && last.content == glyph.content
+                    && last.offset + last.text.chars().count() == glyph.offset =>
+            {
+                last.text.push(glyph.ch)
+            }
+            _ => fragments.push(Fragment {
+                text: glyph.ch.to_string(),
+                gap: *gap,
+                content: glyph.content,
+                offset: glyph.offset,
+                position: glyph.position,
+            }),
+        }
+    }
+
+    fragments
+}
+
+// Size in cells of an image in the reading view, computed from the font size so that the aspect
+// ratio is kept. Images are never enlarged, and small images are shown at the height of the text.
+fn image_size(native: (u32, u32), font_size: (u16, u16), area: Rect) -> (u16, u16) {
+    let (width, height) = (native.0.max(1) as f32, native.1.max(1) as f32);
+    let (font_width, font_height) = (font_size.0 as f32, font_size.1 as f32);
+
+    let scale = if height <= font_height * 2.0 {
+        (font_height / height).min(1.0)
+    } else {
+        1.0_f32
+            .min(area.width as f32 * font_width / width)
+            .min(area.height as f32 * font_height / height)
+    };
+
+    (
+        ((width * scale / font_width).ceil() as u16).clamp(1, area.width.max(1)),
+        ((height * scale / font_height).ceil() as u16).clamp(1, area.height.max(1)),
+    )
+}
+
+// One-line description of an image that cannot be shown
+pub fn image_placeholder(alt: &Option<String>) -> String {
+    match alt {
+        Some(alt) => format!("[Image: {}]", alt),
+        None => "[Image]".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The lines of the text wrapped into the width, with a "-" after the hyphenated lines
+    fn lines(text: &str, width: usize, indent: usize, hyphens: &[usize]) -> Vec<String> {
+        let glyphs = glyphs(text, 0, 0);
+        let mut marks = vec![false; glyphs.len()];
+        for index in hyphens {
+            marks[*index] = true;
+        }
+
+        wrap(&glyphs, width, indent, &marks)
+            .into_iter()
+            .map(|(range, hyphen)| {
+                let line: String = glyphs[range]
+                    .iter()
+                    .map(|x| x.ch)
+                    .filter(|x| *x != SOFT_HYPHEN)
+                    .collect();
+                if hyphen {
+                    line + "-"
+                } else {
+                    line
+                }
+            })
+            .collect()
+    }
+
+    fn breaks(text: &str) -> Vec<bool> {
+        break_opportunities(&glyphs(text, 0, 0))
+    }
+
+    #[test]
+    fn wrap_at_spaces() {
+        assert_eq!(
+            lines("the quick brown fox", 10, 0, &[]),
+            ["the quick", "brown fox"]
+        );
+        // The whitespace at the edges of the lines is removed
+        assert_eq!(lines("  a    b  ", 2, 0, &[]), ["a", "b"]);
+        assert_eq!(lines("", 10, 0, &[]), [""]);
+    }
+
+    #[test]
+    fn wrap_indents_first_line() {
+        assert_eq!(lines("aaa bbb ccc", 8, 2, &[]), ["aaa", "bbb ccc"]);
+        assert_eq!(lines("aaa bbb ccc", 8, 0, &[]), ["aaa bbb", "ccc"]);
+    }
+
+    #[test]
+    fn wrap_splits_long_words() {
+        assert_eq!(lines("abcdefghij", 4, 0, &[]), ["abcd", "efgh", "ij"]);
+        // The split is not before a comma
+        assert_eq!(lines("abc,", 3, 0, &[]), ["ab", "c,"]);
+        // Nor after an opening bracket
+        assert_eq!(lines("a(bc", 2, 0, &[]), ["a", "(b", "c"]);
+    }
+
+    #[test]
+    fn wrap_hyphenates() {
+        // At the hyphenation points, only if the hyphen fits
+        assert_eq!(lines("hyphenation", 7, 0, &[2, 6]), ["hyphen-", "ation"]);
+        assert_eq!(
+            lines("hyphenation", 6, 0, &[2, 6]),
+            ["hy-", "phen-", "ation"]
+        );
+        // At the soft hyphens, which are only shown when the line is broken there
+        assert_eq!(
+            lines("extra\u{ad}ordinary", 8, 0, &[]),
+            ["extra-", "ordinary"]
+        );
+        assert_eq!(lines("extra\u{ad}ordinary", 20, 0, &[]), ["extraordinary"]);
+        // The lines that end with a hyphen are broken after it
+        assert_eq!(
+            lines("well-known fact", 7, 0, &[]),
+            ["well-", "known", "fact"]
+        );
+    }
+
+    #[test]
+    fn break_opportunities_follow_uax14() {
+        assert_eq!(breaks("a b"), [false, false, true]);
+        // Between CJK characters, but not before "。" or after "「"
+        assert_eq!(breaks("漢字。"), [false, true, false]);
+        assert_eq!(breaks("「あ」"), [false, false, false]);
+        // Not before the small kana, "ー" and "、"
+        assert_eq!(breaks("きゃー、"), [false, false, false, false]);
+    }
+
+    #[test]
+    fn kinsoku() {
+        let text = "「きょうは、いいてんきですね。」とかれはいった。ちょっとまってー、といわれた。";
+        let no_start = ['。', '、', '」', 'ゃ', 'ょ', 'っ', 'ー'];
+
+        // The forced splits of the narrow widths keep the rules too
+        for width in 2..=30 {
+            for line in lines(text, width, 0, &[]) {
+                let first = line.chars().next().unwrap();
+                let last = line.chars().last().unwrap();
+                assert!(
+                    !no_start.contains(&first),
+                    "line '{}' starts with '{}' at width {}",
+                    line,
+                    first,
+                    width
+                );
+                assert!(
+                    last != '「',
+                    "line '{}' ends with '「' at width {}",
+                    line,
+                    width
+                );
+            }
+        }
+        assert_eq!(lines("あ。", 2, 0, &[]), ["あ。"]);
+        assert_eq!(lines("あい。", 4, 0, &[]), ["あ", "い。"]);
+    }
+
+    // The rows that the pages start at, for the items given as their paragraph and height. The
+    // items that are more than a row high are images.
+    fn pages(items: &[(usize, u16)], page_height: u16) -> Vec<usize> {
+        let mut layout = Layout {
+            area: (20, page_height),
+            typography: Typography {
+                max_width: None,
+                margin: 0,
+                paragraph_spacing: 0,
+                indent: 0,
+                justify: false,
+                hyphenate: false,
+            },
+            language: None,
+            items: vec![],
+            tops: vec![],
+            paragraphs: vec![],
+            paragraph_count: 0,
+            height: 0,
+            pages: vec![],
+        };
+        for (paragraph, height) in items {
+            layout.paragraph_count = *paragraph;
+            layout.push(match height {
+                1 => Item::Line(vec![]),
+                height => Item::Image {
+                    path: String::new(),
+                    width: 1,
+                    height: *height,
+                    position: 0,
+                },
+            });
+        }
+        layout.paginate();
+
+        layout.pages
+    }
+
+    #[test]
+    fn paginate_fills_pages() {
+        assert_eq!(pages(&[], 4), [0]);
+        assert_eq!(pages(&[(0, 1), (0, 1)], 4), [0]);
+        assert_eq!(
+            pages(&[(0, 1), (1, 1), (2, 1), (3, 1), (4, 1)], 2),
+            [0, 2, 4]
+        );
+    }
+
+    #[test]
+    fn paginate_avoids_widows_and_orphans() {
+        // The last line of a paragraph is not left alone on the next page
+        assert_eq!(pages(&[(0, 1); 5], 4), [0, 3]);
+        // The first line of a paragraph does not end a page
+        assert_eq!(
+            pages(&[(0, 1), (0, 1), (0, 1), (1, 1), (1, 1), (1, 1)], 4),
+            [0, 3]
+        );
+        // A paragraph of two lines is not split, unless it is the whole page
+        assert_eq!(pages(&[(0, 1), (1, 1), (1, 1)], 2), [0, 1]);
+        assert_eq!(pages(&[(0, 1), (0, 1)], 1), [0, 1]);
+    }
+
+    #[test]
+    fn paginate_moves_images() {
+        // An image crossing the end of the page starts the next page
+        assert_eq!(pages(&[(0, 1), (1, 1), (2, 1), (3, 3)], 4), [0, 3]);
+        // An image taller than a page fills its pages
+        assert_eq!(pages(&[(0, 5), (1, 1)], 2), [0, 2, 4]);
+    }
+}
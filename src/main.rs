@@ -1,17 +1,350 @@
 mod app;
+mod layout;
 mod models;
 mod ui;
 mod widgets;
 
-use app::App;
-use std::env;
+use app::{App, ImageMode, StartAt};
+use clap::{Args, Parser, Subcommand};
+use crossterm::{event::DisableMouseCapture, execute};
+use models::{
+    book::Book,
+    config::Config,
+    epub::EpubDoc,
+    export::{export, ExportFormat},
+    highlight::Highlights,
+    identity::book_id,
+};
+use serde_json::json;
+use std::{fs, io, panic, path::Path, process};
+
+// Default memory budget in megabytes for the decoded and encoded images
+const DEFAULT_IMAGE_CACHE: usize = 512;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "A command-line EPUB reader",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The book to read, the library is opened if it is not given
+    file: Option<String>,
+    #[command(flatten)]
+    display: DisplayArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Read a book
+    Read {
+        file: String,
+        #[command(flatten)]
+        start: StartArgs,
+        #[command(flatten)]
+        display: DisplayArgs,
+    },
+    /// Print the metadata, the spine and the manifest of a book
+    Info {
+        file: String,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the table of contents of a book, with the chapter of each entry
+    Toc { file: String },
+    /// Export a book as plain text, Markdown or HTML
+    Export {
+        file: String,
+        #[arg(long, value_enum, default_value = "txt")]
+        format: ExportFormat,
+        /// Export only the chapter in reading order, counted from 1
+        #[arg(long, value_name = "N")]
+        chapter: Option<usize>,
+        /// Write to the file instead of the standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+        /// Extract the images into the directory instead of referencing their path in the book
+        #[arg(long, value_name = "DIR")]
+        images_dir: Option<String>,
+    },
+    /// Print the highlights and notes of a book as Markdown
+    ExportNotes { file: String },
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct StartArgs {
+    /// Start at the chapter in reading order, counted from 1
+    #[arg(long, value_name = "N")]
+    chapter: Option<usize>,
+    /// Start at the first table of contents entry matching the name
+    #[arg(long, value_name = "NAME")]
+    toc_entry: Option<String>,
+    /// Start at the percentage through the book
+    #[arg(long, value_name = "P", value_parser = parse_percent)]
+    percent: Option<f64>,
+}
+
+#[derive(Args)]
+struct DisplayArgs {
+    /// How images are shown, none shows them as text placeholders [default: the image_mode of
+    /// the config, or auto]
+    #[arg(long, value_enum)]
+    images: Option<ImageMode>,
+    /// The memory budget in megabytes for the images
+    #[arg(long, value_name = "MB", default_value_t = DEFAULT_IMAGE_CACHE)]
+    image_cache: usize,
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value.parse().map_err(|_| "not a number".to_string())?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err("must be between 0 and 100".to_string());
+    }
+
+    Ok(percent)
+}
+
+// Exit with an error if the file does not exist, instead of panicking when it is opened
+fn check_file(path: &str) {
+    if !Path::new(path).is_file() {
+        eprintln!("error: '{}' is not a file", path);
+        process::exit(1);
+    }
+}
+
+// Exit with the error of a book that cannot be read
+fn invalid_book(path: &str, err: String) -> ! {
+    eprintln!("error: '{}' is not a valid EPUB: {}", path, err);
+    process::exit(1);
+}
+
+fn read(path: Option<&str>, start: Option<StartAt>, display: DisplayArgs) {
+    let book = path.map(|path| {
+        check_file(path);
+        let book = Book::new(path).unwrap_or_else(|err| invalid_book(path, err));
+        (path, book)
+    });
+
+    // The config is checked before the terminal is taken over, so that the errors can be read
+    let config = Config::load().unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    });
+    let image_mode = display.images.unwrap_or(config.image_mode);
 
-fn main() {
     let mut terminal = ratatui::init();
 
-    let args: Vec<String> = env::args().collect();
-    let app_result = App::new(args[1].as_str()).run(&mut terminal);
+    // The hook of ratatui restores the screen on a panic, but the terminal would still report the
+    // mouse to the shell
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = execute!(io::stdout(), DisableMouseCapture);
+        hook(info);
+    }));
+    let mut app = App::new(book, config, image_mode, display.image_cache * 1024 * 1024);
+    if let Some(Err(err)) = start.map(|x| app.start_at(x)) {
+        ratatui::restore();
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
 
+    app.run(&mut terminal);
     ratatui::restore();
-    app_result
+
+    if let Some(Err(err)) = app.message {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn info(path: &str, json: bool) {
+    let doc = EpubDoc::new(path).unwrap_or_else(|err| invalid_book(path, err));
+    let meta = &doc.meta;
+
+    let mut manifest: Vec<(&String, &(String, String))> = doc.resources.iter().collect();
+    manifest.sort_by(|a, b| a.1 .0.cmp(&b.1 .0));
+    let spine: Vec<(&String, &str)> = doc
+        .spine
+        .iter()
+        .map(|x| (x, doc.resources.get(x).map(|x| x.0.as_str()).unwrap_or("")))
+        .collect();
+
+    if json {
+        let value = json!({
+            "metadata": {
+                "title": meta.title,
+                "creator": meta.creator,
+                "language": meta.language,
+                "publisher": meta.publisher,
+                "identifier": meta.identifier,
+                "unique_identifier": meta.unique_identifier,
+                "cover": meta.cover,
+            },
+            "spine": spine
+                .iter()
+                .map(|(id, path)| json!({ "id": id, "path": path }))
+                .collect::<Vec<_>>(),
+            "manifest": manifest
+                .iter()
+                .map(|(id, (path, media_type))| {
+                    json!({ "id": id, "path": path, "media_type": media_type })
+                })
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+        return;
+    }
+
+    let optional = |x: &Option<String>| x.clone().unwrap_or("-".to_string());
+    let list = |x: &Vec<String>| {
+        if x.is_empty() {
+            "-".to_string()
+        } else {
+            x.join(", ")
+        }
+    };
+    println!("Title:             {}", optional(&meta.title));
+    println!("Creator:           {}", list(&meta.creator));
+    println!("Language:          {}", optional(&meta.language));
+    println!("Publisher:         {}", list(&meta.publisher));
+    println!("Identifier:        {}", list(&meta.identifier));
+    println!("Unique identifier: {}", optional(&meta.unique_identifier));
+    println!("Cover:             {}", optional(&meta.cover));
+
+    println!("\nSpine:");
+    for (index, (id, path)) in spine.iter().enumerate() {
+        println!("  {:>3}. {} ({})", index + 1, path, id);
+    }
+
+    println!("\nManifest:");
+    for (id, (path, media_type)) in manifest {
+        println!("  {} ({}, {})", path, id, media_type);
+    }
+}
+
+fn toc(path: &str) {
+    let doc = EpubDoc::new(path).unwrap_or_else(|err| invalid_book(path, err));
+    let chapters: Vec<&str> = doc
+        .spine
+        .iter()
+        .filter_map(|x| doc.resources.get(x).map(|x| x.0.as_str()))
+        .collect();
+
+    // The chapter is the one to pass to read --chapter
+    for (link, title) in &doc.toc {
+        let path = link.split_once('#').map_or(link.as_str(), |x| x.0);
+        match chapters.iter().position(|x| *x == path) {
+            Some(index) => println!("{}\t{}", index + 1, title),
+            None => println!("-\t{}", title),
+        }
+    }
+}
+
+fn export_book(
+    path: &str,
+    format: ExportFormat,
+    chapter: Option<usize>,
+    output: Option<&str>,
+    images_dir: Option<&str>,
+) {
+    let book = Book::new(path).unwrap_or_else(|err| invalid_book(path, err));
+
+    let pages = match chapter {
+        Some(chapter) => match chapter.checked_sub(1).and_then(|x| book.order.get(x)) {
+            Some(page) => vec![page.clone()],
+            None => {
+                eprintln!(
+                    "error: chapter {} not found, the book has {} chapters",
+                    chapter,
+                    book.order.len()
+                );
+                process::exit(1);
+            }
+        },
+        None => book.order.clone(),
+    };
+
+    let exported = export(&book, &pages, format, images_dir.map(Path::new));
+    let result = exported.and_then(|exported| match output {
+        Some(output) => fs::write(output, exported),
+        None => {
+            print!("{}", exported);
+            Ok(())
+        }
+    });
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+// Print the highlights and notes of the book as Markdown
+fn export_notes(path: &str) {
+    let book = Book::new(path).unwrap_or_else(|err| invalid_book(path, err));
+    let book_hash = book_id(path, book.identifier.as_deref()).unwrap_or_else(|err| {
+        eprintln!("error: could not find the saved state of the book: {}", err);
+        process::exit(1);
+    });
+    let highlights = Highlights::load(&book_hash).unwrap_or_else(|err| {
+        eprintln!("error: could not load the highlights: {}", err);
+        process::exit(1);
+    });
+
+    print!("{}", highlights.to_markdown(&book));
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => read(cli.file.as_deref(), None, cli.display),
+        Some(Command::Read {
+            file,
+            start,
+            display,
+        }) => {
+            let start = if let Some(chapter) = start.chapter {
+                Some(StartAt::Chapter(chapter))
+            } else if let Some(name) = start.toc_entry {
+                Some(StartAt::TocEntry(name))
+            } else {
+                start.percent.map(StartAt::Percent)
+            };
+
+            read(Some(&file), start, display)
+        }
+        Some(Command::Info { file, json }) => {
+            check_file(&file);
+            info(&file, json);
+        }
+        Some(Command::Toc { file }) => {
+            check_file(&file);
+            toc(&file);
+        }
+        Some(Command::Export {
+            file,
+            format,
+            chapter,
+            output,
+            images_dir,
+        }) => {
+            check_file(&file);
+            export_book(
+                &file,
+                format,
+                chapter,
+                output.as_deref(),
+                images_dir.as_deref(),
+            );
+        }
+        Some(Command::ExportNotes { file }) => {
+            check_file(&file);
+            export_notes(&file);
+        }
+    }
 }
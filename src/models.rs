@@ -1,5 +1,20 @@
 pub mod book;
+pub mod bookmark;
+pub mod cache;
+pub mod config;
 pub mod epub;
+pub mod export;
+pub mod fuzzy;
+pub mod highlight;
+pub mod history;
+pub mod hyphenator;
+pub mod identity;
 pub mod image;
+pub mod image_cache;
+pub mod keymap;
 pub mod page;
 pub mod reading_position;
+pub mod reading_speed;
+pub mod search;
+pub mod theme;
+pub mod typography;
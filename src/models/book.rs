@@ -1,21 +1,36 @@
 use regex::Regex;
-use std::{collections::HashMap, path::Path};
+use std::{cmp::Reverse, collections::HashMap, path::Path};
 
-use super::{epub::EpubDoc, image::Image, page::Page};
+use super::{
+    epub::EpubDoc,
+    fuzzy::fuzzy_match,
+    image::Image,
+    page::{Direction, Page},
+};
 
+// The default book is empty, which is used until a book is opened from the library
+#[derive(Default)]
 pub struct Book {
     pub title: String,
+    pub author: Option<String>,
+    // The unique identifier of the book, or its first identifier
+    pub identifier: Option<String>,
+    // The language of the book, which is the language of the text without a lang attribute
+    pub language: Option<String>,
+    // Whether the book is read from right to left, by its page progression or its language
+    pub rtl: bool,
     pub cover: Option<String>,
     pub pages: HashMap<String, Page>,
     pub images: HashMap<String, Image>,
     pub order: Vec<String>,
-    pub toc: Vec<(String, String)>,
+    // The page, the position in its text and the title of each entry
+    pub toc: Vec<(String, usize, String)>,
 }
 
 impl Book {
-    pub fn new(path: &str) -> Book {
+    pub fn new(path: &str) -> Result<Book, String> {
         // Open the book
-        let mut doc = EpubDoc::new(path);
+        let mut doc = EpubDoc::new(path)?;
 
         // Extract all the images
         let mut images = HashMap::new();
@@ -23,8 +38,9 @@ impl Book {
         let res = doc.resources.clone();
         for (_, value) in res {
             if img_reg.is_match(&value.1) {
-                let content = doc.get_raw_by_path(&value.0);
-                images.insert(value.0.clone(), Image::new(content));
+                // An image missing from the archive is shown as an empty image
+                let content = doc.get_raw_by_path(&value.0).unwrap_or_default();
+                images.insert(value.0.clone(), Image::new(content, &value.1));
             }
         }
 
@@ -34,31 +50,158 @@ impl Book {
 
         let spine = doc.spine.clone();
         for elem in spine {
-            let content = doc.get_by_id(&elem);
+            let content = doc.get_by_id(&elem)?;
             let path = Path::new(&doc.resources.get(&elem).unwrap().0);
             let path_string = path.to_str().unwrap().to_string();
             order.push(path_string.clone());
 
-            pages.insert(
-                path_string,
-                Page::new(
-                    content,
-                    doc.toc
-                        .iter()
-                        .find(|x| x.0 == path.to_str().unwrap())
-                        .map(|x| x.1.clone()),
-                    path.as_ref(),
-                ),
-            );
+            let mut page = Page::new(
+                content,
+                doc.toc
+                    .iter()
+                    .find(|x| x.0.split_once('#').map_or(x.0.as_str(), |x| x.0) == path_string)
+                    .map(|x| x.1.clone()),
+                path,
+            )?;
+
+            // Inline SVGs are rasterized just like the SVG resources
+            for (id, source) in page.svgs.drain(..) {
+                images.insert(id, Image::new(source.into_bytes(), "image/svg+xml"));
+            }
+
+            pages.insert(path_string, page);
         }
 
-        Book {
-            title: doc.meta.title.unwrap(),
+        let mut book = Book {
+            // The books without a title are named after their file
+            title: doc.meta.title.unwrap_or_else(|| {
+                Path::new(path)
+                    .file_stem()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            }),
+            author: (!doc.meta.creator.is_empty()).then(|| doc.meta.creator.join(", ")),
+            identifier: doc
+                .meta
+                .unique_identifier
+                .or_else(|| doc.meta.identifier.into_iter().next()),
+            rtl: doc.rtl
+                || doc
+                    .meta
+                    .language
+                    .as_deref()
+                    .is_some_and(|x| Direction::of_language(x) == Direction::Rtl),
+            language: doc.meta.language,
             cover: doc.meta.cover,
             pages,
             images,
-            toc: doc.toc,
+            toc: vec![],
             order,
+        };
+        // The entries are placed at their element, or at the start of their page
+        book.toc = doc
+            .toc
+            .into_iter()
+            .map(|(link, title)| {
+                let (page, position) = book.link_position(&link).unwrap_or_else(|| {
+                    let path = link.split_once('#').map_or(link.as_str(), |x| x.0);
+                    (path.to_string(), 0)
+                });
+                (page, position, title)
+            })
+            .collect();
+
+        Ok(book)
+    }
+
+    // The position in characters through the book, fraction is how far through the page
+    pub fn position(&self, page: &str, fraction: f64) -> usize {
+        let before: usize = self
+            .order
+            .iter()
+            .take_while(|x| *x != page)
+            .map(|x| self.pages.get(x).unwrap().length)
+            .sum();
+
+        before + (self.pages.get(page).unwrap().length as f64 * fraction) as usize
+    }
+
+    // The page and the position in its text that the link points to, like "OEBPS/ch2.xhtml#note",
+    // which is the start of the page if the element is not found
+    pub fn link_position(&self, link: &str) -> Option<(String, usize)> {
+        let (path, id) = link.split_once('#').unwrap_or((link, ""));
+        let page = self.pages.get(path)?;
+        let position = page
+            .anchors
+            .get(id)
+            .map(|x| page.position(*x, 0))
+            .unwrap_or(0);
+
+        Some((path.to_string(), position))
+    }
+
+    // The page and the position in its text of the position in characters through the book
+    pub fn locate(&self, mut position: usize) -> Option<(&String, usize)> {
+        for page in &self.order {
+            let length = self.pages.get(page).unwrap().length;
+            if position < length {
+                return Some((page, position));
+            }
+            position -= length;
         }
+
+        self.order
+            .last()
+            .map(|x| (x, self.pages.get(x).unwrap().length))
+    }
+
+    // The number of characters of the text of all the pages in the book
+    pub fn length(&self) -> usize {
+        self.order
+            .iter()
+            .map(|x| self.pages.get(x).unwrap().length)
+            .sum()
+    }
+
+    // The index of the last TOC entry at or before the position in the page, which is the last
+    // entry of an earlier page if no entry of the page starts before the position
+    pub fn toc_entry(&self, page: &str, position: usize) -> Option<usize> {
+        let index = self.order.iter().position(|x| x == page)?;
+
+        self.order[..=index].iter().rev().find_map(|x| {
+            self.toc
+                .iter()
+                .enumerate()
+                .filter(|(_, y)| y.0 == *x && (x != page || y.1 <= position))
+                .max_by_key(|(_, y)| y.1)
+                .map(|(index, _)| index)
+        })
+    }
+
+    // The title of the last TOC entry at or before the position in the page
+    pub fn toc_section(&self, page: &str, position: usize) -> Option<&str> {
+        self.toc_entry(page, position)
+            .map(|x| self.toc[x].2.as_str())
+    }
+
+    // The indices of the TOC entries whose titles match the filter, with the indices of the
+    // matched characters of the titles. The best matches come first, and every entry is kept in
+    // order if the filter is empty.
+    pub fn filter_toc(&self, filter: &str) -> Vec<(usize, Vec<usize>)> {
+        let mut entries: Vec<(i64, usize, Vec<usize>)> = self
+            .toc
+            .iter()
+            .enumerate()
+            .filter_map(|(index, x)| {
+                fuzzy_match(filter, &x.2).map(|(score, matched)| (score, index, matched))
+            })
+            .collect();
+        // The sort is stable, so the entries with the same score stay in order
+        entries.sort_by_key(|x| Reverse(x.0));
+
+        entries
+            .into_iter()
+            .map(|(_, index, matched)| (index, matched))
+            .collect()
     }
 }
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+};
+
+use super::cache::cache_file;
+
+#[derive(Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub page: String,
+    // The position in characters in the text of the page, see Page::text
+    pub position: usize,
+    pub excerpt: String,
+}
+
+// The bookmarks of a book, saved next to its reading position
+#[derive(Serialize, Deserialize, Default)]
+pub struct Bookmarks {
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn save(&self, book_hash: &str) -> io::Result<()> {
+        let bookmarks_file = cache_file(&format!("{}.bookmarks.json", book_hash))?;
+
+        let file = File::create(bookmarks_file)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self)?;
+
+        Ok(())
+    }
+
+    pub fn load(book_hash: &str) -> io::Result<Self> {
+        let bookmarks_file = cache_file(&format!("{}.bookmarks.json", book_hash))?;
+
+        if !bookmarks_file.exists() {
+            return Ok(Bookmarks::default());
+        }
+
+        let file = File::open(bookmarks_file)?;
+        let reader = BufReader::new(file);
+        let bookmarks = serde_json::from_reader(reader)?;
+
+        Ok(bookmarks)
+    }
+}
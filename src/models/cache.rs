@@ -0,0 +1,12 @@
+use std::{fs, io, path::PathBuf};
+
+// The path of a file in the cache directory of ers, the directory is created if needed
+pub fn cache_file(name: &str) -> io::Result<PathBuf> {
+    let mut cache_dir = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+    cache_dir.push("ers");
+    fs::create_dir_all(&cache_dir)?;
+
+    cache_dir.push(name);
+
+    Ok(cache_dir)
+}
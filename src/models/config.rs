@@ -0,0 +1,167 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use super::{
+    keymap::Keymap,
+    theme::{supports_italic, supports_true_color, supports_underline, Theme, BUILT_IN_THEMES},
+    typography::MIN_WIDTH,
+};
+use crate::app::ImageMode;
+
+const CONFIG_FILE: &str = "config.toml";
+
+// The screen that a book opens at
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartScreen {
+    // The saved reading position, or the book info if the book was not read yet
+    Reading,
+    Info,
+}
+
+// The space between the border of the reading screen and the text, in cells
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Margins {
+    pub horizontal: u16,
+    pub vertical: u16,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Margins {
+            horizontal: 2,
+            vertical: 1,
+        }
+    }
+}
+
+// The layout of the text of the books, which can be changed for each book while reading
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Text {
+    // The maximum number of cells of a line, the text fills the screen if it is not set
+    pub max_width: Option<u16>,
+    // The number of blank lines between paragraphs
+    pub paragraph_spacing: usize,
+    // The indentation of the first line of the paragraphs, in cells
+    pub indent: usize,
+    pub justify: bool,
+    // Whether words are hyphenated with the patterns of their language, the soft hyphens of the
+    // books are always used
+    pub hyphenate: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub image_mode: ImageMode,
+    // The number of rows that a scroll moves
+    pub scroll_step: usize,
+    pub start_screen: StartScreen,
+    // Whether the mouse is captured to scroll and click, which stops the terminal from selecting
+    // text
+    pub mouse: bool,
+    pub margins: Margins,
+    pub text: Text,
+    pub theme: String,
+    // The user-defined themes, with the colors that they change of their base theme
+    pub themes: BTreeMap<String, BTreeMap<String, String>>,
+    // Whether the terminal can show true colors, italic and underlined text, they are detected
+    // if they are not set
+    pub true_color: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub keys: Keymap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            image_mode: ImageMode::Auto,
+            scroll_step: 1,
+            start_screen: StartScreen::Reading,
+            mouse: true,
+            margins: Margins::default(),
+            text: Text::default(),
+            theme: "dark".to_string(),
+            themes: BTreeMap::new(),
+            true_color: None,
+            italic: None,
+            underline: None,
+            keys: Keymap::default(),
+        }
+    }
+}
+
+impl Config {
+    // The config file in the config directory of the user, like ~/.config/ers/config.toml
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|x| x.join("ers").join(CONFIG_FILE))
+    }
+
+    // The default config is used if there is no config file
+    pub fn load() -> Result<Config, String> {
+        let Some(path) = Config::path() else {
+            return Ok(Config::default());
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(format!("cannot read {}: {}", path.display(), err)),
+        };
+
+        let config: Config = toml::from_str(&content)
+            .map_err(|err| format!("invalid config {}: {}", path.display(), err))?;
+        config
+            .validate()
+            .map_err(|err| format!("invalid config {}: {}", path.display(), err))?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.scroll_step == 0 {
+            return Err("scroll_step must be at least 1".to_string());
+        }
+        if self.text.max_width.is_some_and(|x| x < MIN_WIDTH) {
+            return Err(format!("text.max_width must be at least {}", MIN_WIDTH));
+        }
+
+        for name in self.themes.keys() {
+            self.theme(name)
+                .map_err(|err| format!("theme '{}': {}", name, err))?;
+        }
+        self.theme(&self.theme)?;
+
+        self.keys.validate()
+    }
+
+    // The built-in themes and then the user-defined ones
+    pub fn theme_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN_THEMES.iter().map(|x| x.to_string()).collect();
+        for name in self.themes.keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+
+        names
+    }
+
+    // The theme as the terminal can show it, a user-defined theme replaces the built-in theme
+    // with the same name
+    pub fn theme(&self, name: &str) -> Result<Theme, String> {
+        let theme = match self.themes.get(name) {
+            Some(colors) => Theme::user_defined(colors)?,
+            None => Theme::built_in(name).ok_or(format!("unknown theme '{}'", name))?,
+        };
+
+        Ok(theme.for_terminal(
+            self.true_color.unwrap_or_else(supports_true_color),
+            self.italic.unwrap_or_else(supports_italic),
+            self.underline.unwrap_or_else(supports_underline),
+        ))
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     path::Path,
 };
 
-use regex::Regex;
+use roxmltree::Document;
 use zip::ZipArchive;
 
 pub struct Metadata {
@@ -15,6 +15,8 @@ pub struct Metadata {
     pub creator: Vec<String>,
     pub publisher: Vec<String>,
     pub identifier: Vec<String>,
+    // The identifier that the package names as unique-identifier
+    pub unique_identifier: Option<String>,
 }
 
 impl Metadata {
@@ -26,6 +28,7 @@ impl Metadata {
             creator: vec![],
             publisher: vec![],
             identifier: vec![],
+            unique_identifier: None,
         }
     }
 }
@@ -37,64 +40,57 @@ pub struct EpubDoc {
     pub resources: HashMap<String, (String, String)>,
     // A vector of id
     pub spine: Vec<String>,
-    // The first string is the path, and the second string is the title
+    // Whether the pages turn from right to left, from the page-progression-direction of the spine
+    pub rtl: bool,
+    // The first string is the path with the fragment of the entry, and the second string is the
+    // title
     pub toc: Vec<(String, String)>,
 }
 
 impl EpubDoc {
-    pub fn new(path: &str) -> Self {
-        let fname = std::path::Path::new(path);
-        let file = fs::File::open(fname).unwrap();
-
-        let mut archive = zip::ZipArchive::new(file).unwrap();
+    // A malformed book is an error instead of a panic, so that it can be reported
+    pub fn new(path: &str) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|err| err.to_string())?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|err| err.to_string())?;
 
         // get the metadata of epub file
-        let mut content = String::new();
-        {
-            let meta = &mut archive
-                .by_name("META-INF/container.xml")
-                .expect("A valid EPUB format");
-            meta.read_to_string(&mut content).unwrap();
-        }
-        let meta = roxmltree::Document::parse(content.as_str()).unwrap();
+        let content = read_file(&mut archive, "META-INF/container.xml")?;
+        let meta = parse_xml(&content, "META-INF/container.xml")?;
 
         // get the path of content.opf
-        let root_file = meta
+        let content_opf_path = meta
             .descendants()
             .find(|n| n.has_tag_name("rootfile"))
-            .expect("Only one root file");
-        let content_opf_path = root_file
-            .attribute("full-path")
-            .expect("A path to content.opf");
+            .and_then(|n| n.attribute("full-path"))
+            .ok_or("the container has no root file")?;
 
         // get the content.opf
-        let mut content = String::new();
-        {
-            let content_opf = &mut archive
-                .by_name(content_opf_path)
-                .expect("A context.opf file");
-            content_opf.read_to_string(&mut content).unwrap();
-        }
-        let content_opf: roxmltree::Document<'_> =
-            roxmltree::Document::parse(content.as_str()).unwrap();
+        let content = read_file(&mut archive, content_opf_path)?;
+        let content_opf = parse_xml(&content, content_opf_path)?;
 
         // parse metadata of the book
         let mut parsed_meta = Metadata::new();
+        let unique_identifier = content_opf.root_element().attribute("unique-identifier");
         let meta = content_opf
             .descendants()
             .find(|n| n.has_tag_name("metadata"))
-            .expect("Atleast one metadata");
+            .ok_or("the package has no metadata")?;
         for ele in meta.children() {
+            let text = ele.text().unwrap_or_default().to_string();
             match ele.tag_name().name() {
-                "title" => parsed_meta.title = Some(ele.text().unwrap().to_string()),
-                "language" => parsed_meta.language = Some(ele.text().unwrap().to_string()),
-                "identifier" => parsed_meta.identifier.push(ele.text().unwrap().to_string()),
-                "publisher" => parsed_meta.publisher.push(ele.text().unwrap().to_string()),
-                "creator" => parsed_meta.creator.push(ele.text().unwrap().to_string()),
-                "meta" => {
-                    if ele.attribute("name").is_some_and(|n| n == "cover") {
-                        parsed_meta.cover = Some(ele.attribute("content").unwrap().to_string())
+                "title" => parsed_meta.title = Some(text),
+                "language" => parsed_meta.language = Some(text),
+                "identifier" => {
+                    let identifier = text.trim().to_string();
+                    if unique_identifier.is_some() && ele.attribute("id") == unique_identifier {
+                        parsed_meta.unique_identifier = Some(identifier.clone());
                     }
+                    parsed_meta.identifier.push(identifier);
+                }
+                "publisher" => parsed_meta.publisher.push(text),
+                "creator" => parsed_meta.creator.push(text),
+                "meta" if ele.attribute("name").is_some_and(|n| n == "cover") => {
+                    parsed_meta.cover = ele.attribute("content").map(|x| x.to_string())
                 }
                 _ => (),
             }
@@ -103,9 +99,8 @@ impl EpubDoc {
         // parse all the documents in the epub
         let mut base_path = Path::new(content_opf_path)
             .parent()
-            .unwrap()
-            .to_str()
-            .unwrap()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default()
             .to_string();
         if !base_path.is_empty() {
             base_path = format!("{}/", base_path);
@@ -114,101 +109,118 @@ impl EpubDoc {
         let manifest = content_opf
             .descendants()
             .find(|n| n.has_tag_name("manifest"))
-            .expect("At least one manifest");
+            .ok_or("the package has no manifest")?;
         for ele in manifest.children() {
             if ele.is_element() {
-                let key = ele.attribute("id").unwrap().to_string();
-                let path = base_path.clone() + ele.attribute("href").unwrap();
+                let (Some(key), Some(href)) = (ele.attribute("id"), ele.attribute("href")) else {
+                    return Err("an item of the manifest has no id or href".to_string());
+                };
+                let key = key.to_string();
+                let path = base_path.clone() + href;
 
                 if parsed_meta.cover.clone().is_some_and(|x| x == key) {
                     parsed_meta.cover = Some(path.clone());
                 }
 
-                resources.insert(
-                    key,
-                    (path, ele.attribute("media-type").unwrap().to_string()),
-                );
+                let media_type = ele.attribute("media-type").unwrap_or_default();
+                resources.insert(key, (path, media_type.to_string()));
             }
         }
 
         // parse the spine
-        let spine: Vec<String> = content_opf
+        let spine_node = content_opf
             .descendants()
             .find(|n| n.has_tag_name("spine"))
-            .expect("Atleast one spine")
-            .children()
-            .filter(|n| n.is_element())
-            .map(|n| n.attribute("idref").unwrap().to_string())
-            .collect();
+            .ok_or("the package has no spine")?;
+        let rtl = spine_node.attribute("page-progression-direction") == Some("rtl");
+        let mut spine = vec![];
+        for ele in spine_node.children().filter(|n| n.is_element()) {
+            let idref = ele
+                .attribute("idref")
+                .ok_or("an item of the spine has no idref")?;
+            if !resources.contains_key(idref) {
+                return Err(format!("the spine refers to the missing item '{}'", idref));
+            }
+            spine.push(idref.to_string());
+        }
 
         // parse toc
         let mut toc = vec![];
-        if resources.get("ncx").is_some() {
-            let mut content = String::new();
-            let toc_ncx = &mut archive
-                .by_name(resources.get("ncx").unwrap().0.as_str())
-                .unwrap();
-            toc_ncx.read_to_string(&mut content).unwrap();
-            let toc_ncx: roxmltree::Document<'_> =
-                roxmltree::Document::parse(content.as_str()).unwrap();
+        if let Some((ncx_path, _)) = resources.get("ncx") {
+            let content = read_file(&mut archive, ncx_path)?;
+            let toc_ncx = parse_xml(&content, ncx_path)?;
 
             let nav_map = toc_ncx
                 .descendants()
                 .find(|n| n.has_tag_name("navMap"))
-                .unwrap();
-            let re = Regex::new(r"#.*$").unwrap();
+                .ok_or("the table of contents has no navMap")?;
             for ele in nav_map.children() {
                 if ele.is_element() {
+                    // The entries without a target or a label are skipped
                     let path = ele
                         .descendants()
                         .find(|n| n.has_tag_name("content"))
-                        .unwrap()
-                        .attribute("src")
-                        .unwrap()
-                        .to_string();
-                    let path = base_path.clone() + re.replace_all(&path, "").into_owned().as_str();
-
-                    toc.push((
-                        path,
-                        ele.descendants()
-                            .find(|n| n.has_tag_name("text"))
-                            .unwrap()
-                            .text()
-                            .unwrap()
-                            .to_string(),
-                    ));
+                        .and_then(|n| n.attribute("src"));
+                    let title = ele
+                        .descendants()
+                        .find(|n| n.has_tag_name("text"))
+                        .and_then(|n| n.text());
+                    let (Some(path), Some(title)) = (path, title) else {
+                        continue;
+                    };
+                    // The fragment is kept, as several entries can point into one page
+                    let path = base_path.clone() + path;
+
+                    toc.push((path, title.to_string()));
                 }
             }
         }
 
-        EpubDoc {
+        Ok(EpubDoc {
             meta: parsed_meta,
             resources,
             archive,
             spine,
+            rtl,
             toc,
-        }
+        })
     }
 
-    pub fn get_by_id(&mut self, id: &String) -> String {
+    pub fn get_by_id(&mut self, id: &String) -> Result<String, String> {
         self.get_by_path(&self.resources.get(id).unwrap().0.clone())
     }
 
-    pub fn get_by_path(&mut self, path: &String) -> String {
-        let mut content = String::new();
-
-        let mut file = self.archive.by_name(path).unwrap();
-        file.read_to_string(&mut content).unwrap();
-
-        content
+    pub fn get_by_path(&mut self, path: &str) -> Result<String, String> {
+        read_file(&mut self.archive, path)
     }
 
-    pub fn get_raw_by_path(&mut self, path: &String) -> Vec<u8> {
+    pub fn get_raw_by_path(&mut self, path: &str) -> Result<Vec<u8>, String> {
         let mut content = vec![];
 
-        let mut file = self.archive.by_name(path).unwrap();
-        file.read_to_end(&mut content).unwrap();
+        let mut file = self
+            .archive
+            .by_name(path)
+            .map_err(|err| format!("{}: {}", path, err))?;
+        file.read_to_end(&mut content)
+            .map_err(|err| format!("{}: {}", path, err))?;
 
-        content
+        Ok(content)
     }
 }
+
+// The text of the file in the archive
+fn read_file(archive: &mut ZipArchive<File>, path: &str) -> Result<String, String> {
+    let mut content = String::new();
+
+    let mut file = archive
+        .by_name(path)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    file.read_to_string(&mut content)
+        .map_err(|err| format!("{}: {}", path, err))?;
+
+    Ok(content)
+}
+
+fn parse_xml<'a>(content: &'a str, path: &str) -> Result<Document<'a>, String> {
+    Document::parse(content).map_err(|err| format!("{}: {}", path, err))
+}
@@ -0,0 +1,425 @@
+use clap::ValueEnum;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    book::Book,
+    page::{ContentType, TextStyle},
+};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    Txt,
+    Md,
+    Html,
+}
+
+// A page is exported as blocks, which are separated by the line breaks, the list items and the
+// images of the page
+enum Block<'a> {
+    Paragraph(Vec<&'a ContentType>),
+    Heading(u8, Vec<&'a ContentType>),
+    ListItem(Option<usize>, Vec<&'a ContentType>),
+    Image { path: &'a str, alt: Option<&'a str> },
+}
+
+// The blocks of the page, with the ids of the elements that start in them, see Page::anchors
+fn blocks<'a>(
+    content: &'a [ContentType],
+    anchors: &'a HashMap<String, usize>,
+) -> Vec<(Vec<&'a str>, Block<'a>)> {
+    let mut blocks = vec![];
+    let mut inline = vec![];
+    let mut list_item = None;
+    let mut ids = vec![];
+    // The ids by the element that they start at, in order
+    let mut starts: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    for (id, index) in anchors {
+        starts.entry(*index).or_default().push(id);
+    }
+    for x in starts.values_mut() {
+        x.sort_unstable();
+    }
+
+    fn finish<'a>(
+        blocks: &mut Vec<(Vec<&'a str>, Block<'a>)>,
+        inline: &mut Vec<&'a ContentType>,
+        list_item: &mut Option<Option<usize>>,
+        ids: &mut Vec<&'a str>,
+    ) {
+        let inline = std::mem::take(inline);
+
+        let block = if let Some(number) = list_item.take() {
+            Block::ListItem(number, inline)
+        } else if let Some(level) = heading_level(&inline) {
+            Block::Heading(level, inline)
+        } else if !inline.is_empty() {
+            Block::Paragraph(inline)
+        } else {
+            // The ids are kept for the next block
+            return;
+        };
+        blocks.push((std::mem::take(ids), block));
+    }
+
+    for (index, i) in content.iter().enumerate() {
+        // The ids of the elements are in the block of the element, which starts after the block
+        // before it is finished
+        if !matches!(i, ContentType::Text { .. }) {
+            finish(&mut blocks, &mut inline, &mut list_item, &mut ids);
+        }
+        ids.extend(starts.remove(&index).unwrap_or_default());
+
+        match i {
+            ContentType::Text { .. } => inline.push(i),
+            ContentType::LineBreak | ContentType::Anchor(_) => (),
+            ContentType::ListItem { number } => list_item = Some(*number),
+            ContentType::Image { path, alt } | ContentType::Img { path, alt } => {
+                blocks.push((
+                    std::mem::take(&mut ids),
+                    Block::Image {
+                        path,
+                        alt: alt.as_deref(),
+                    },
+                ));
+            }
+        }
+    }
+    finish(&mut blocks, &mut inline, &mut list_item, &mut ids);
+    // The ids at the end of the page
+    ids.extend(starts.into_values().flatten());
+    if !ids.is_empty() {
+        blocks.push((ids, Block::Paragraph(vec![])));
+    }
+
+    blocks
+}
+
+// The id of the element of the page in the exported book, or of the page without an element. The
+// pages are exported together, so the ids are prefixed with the page.
+fn anchor_id(page: &str, id: Option<&str>) -> String {
+    match id {
+        Some(id) => format!("{}_{}", page, id),
+        None => page.to_string(),
+    }
+    .replace(['/', '#'], "_")
+}
+
+// The level of the heading if all the text is a heading
+fn heading_level(inline: &[&ContentType]) -> Option<u8> {
+    let mut level = None;
+
+    for i in inline {
+        match i {
+            ContentType::Text {
+                style: TextStyle {
+                    heading: Some(x), ..
+                },
+                ..
+            } => level = Some(*x),
+            _ => return None,
+        }
+    }
+
+    level
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '[' | ']' | '`' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+// Put the Markdown emphasis markers around the text, but not around the spaces at its edges, as an
+// emphasis is not closed after a space
+fn emphasize(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+
+    format!(
+        "{}{}{}{}{}",
+        &text[..start],
+        marker,
+        trimmed,
+        marker,
+        &text[start + trimmed.len()..]
+    )
+}
+
+struct Exporter<'a> {
+    book: &'a Book,
+    // The exported pages, which the links into the book point to
+    pages: &'a [String],
+    format: ExportFormat,
+    // The images are extracted into this directory if it is set
+    images_dir: Option<&'a Path>,
+    output: String,
+    // Whether the last block was an item of an ordered or unordered list
+    list: Option<bool>,
+}
+
+impl Exporter<'_> {
+    // The links into the book point to the element in the exported pages, and are dropped if their
+    // page is not exported. The other links are kept.
+    fn link(&self, href: &str) -> Option<String> {
+        let (path, id) = href.split_once('#').unwrap_or((href, ""));
+        let Some(page) = self.book.pages.get(path) else {
+            return Some(href.to_string());
+        };
+        if !self.pages.iter().any(|x| x == path) {
+            return None;
+        }
+        let id = Some(id).filter(|x| page.anchors.contains_key(*x));
+
+        Some(format!("#{}", anchor_id(path, id)))
+    }
+
+    // The targets of the links at the start of a block
+    fn targets(&self, ids: &[&str], page: &str) -> String {
+        ids.iter()
+            .map(|x| format!("<a id=\"{}\"></a>", escape_html(&anchor_id(page, Some(x)))))
+            .collect()
+    }
+
+    fn inline(&self, inline: &[&ContentType]) -> String {
+        let mut output = String::new();
+
+        for i in inline {
+            let ContentType::Text {
+                text,
+                style,
+                hints,
+                href,
+                ..
+            } = i
+            else {
+                continue;
+            };
+            let href = href.as_deref().and_then(|x| self.link(x));
+
+            output += &match self.format {
+                // The text has no links
+                ExportFormat::Txt => match hints {
+                    Some(hints) => format!("{}({})", text, hints),
+                    None => text.clone(),
+                },
+                ExportFormat::Md => {
+                    let text = escape_markdown(text);
+                    match (href, hints, style) {
+                        (Some(href), ..) => format!("[{}]({})", text, href),
+                        // Markdown has no ruby, but it allows HTML
+                        (_, Some(hints), _) => {
+                            format!("<ruby>{}<rt>{}</rt></ruby>", text, escape_html(hints))
+                        }
+                        (_, _, style) => match (style.bold, style.italic) {
+                            (true, true) => emphasize(&text, "***"),
+                            (true, false) => emphasize(&text, "**"),
+                            (false, true) => emphasize(&text, "*"),
+                            (false, false) => text,
+                        },
+                    }
+                }
+                ExportFormat::Html => {
+                    let text = escape_html(text);
+                    match (href, hints, style) {
+                        (Some(href), ..) => {
+                            format!("<a href=\"{}\">{}</a>", escape_html(&href), text)
+                        }
+                        (_, Some(hints), _) => {
+                            format!("<ruby>{}<rt>{}</rt></ruby>", text, escape_html(hints))
+                        }
+                        (_, _, style) => {
+                            let mut text = text;
+                            if style.underline {
+                                text = format!("<u>{}</u>", text);
+                            }
+                            if style.italic {
+                                text = format!("<em>{}</em>", text);
+                            }
+                            if style.bold {
+                                text = format!("<strong>{}</strong>", text);
+                            }
+                            text
+                        }
+                    }
+                }
+            };
+        }
+
+        output
+    }
+
+    // Where the image is referenced, the image is written into the images directory if it is set
+    fn image_source(&self, path: &str) -> io::Result<String> {
+        let Some(images_dir) = self.images_dir else {
+            return Ok(path.to_string());
+        };
+
+        // Inline SVGs have the path of their page and an id
+        let mut name = path.replace(['/', '#'], "_");
+        if path.contains("#svg-") {
+            name += ".svg";
+        }
+        let file: PathBuf = images_dir.join(name);
+
+        if let Some(image) = self.book.images.get(path) {
+            fs::create_dir_all(images_dir)?;
+            fs::write(&file, image.content())?;
+        }
+
+        Ok(file.to_string_lossy().to_string())
+    }
+
+    fn end_list(&mut self) {
+        match (self.list.take(), self.format) {
+            (Some(true), ExportFormat::Html) => self.output += "</ol>\n",
+            (Some(false), ExportFormat::Html) => self.output += "</ul>\n",
+            (Some(_), _) => self.output += "\n",
+            (None, _) => (),
+        }
+    }
+
+    fn block(&mut self, ids: &[&str], page: &str, block: &Block) -> io::Result<()> {
+        // The blocks of only ids have nothing to show in the plain text
+        if matches!(block, Block::Paragraph(x) if x.is_empty()) && self.format == ExportFormat::Txt
+        {
+            return Ok(());
+        }
+
+        let ordered = match block {
+            Block::ListItem(number, _) => Some(number.is_some()),
+            _ => None,
+        };
+        if self.list.is_some() && self.list != ordered {
+            self.end_list();
+        }
+
+        // The plain text has no links to the elements
+        let targets = match self.format {
+            ExportFormat::Txt => String::new(),
+            _ => self.targets(ids, page),
+        };
+
+        match block {
+            Block::Paragraph(inline) => {
+                let text = targets + &self.inline(inline);
+                self.output += &match self.format {
+                    ExportFormat::Html => format!("<p>{}</p>\n", text),
+                    _ => format!("{}\n\n", text),
+                };
+            }
+            Block::Heading(level, inline) => {
+                let text = targets + &self.inline(inline);
+                self.output += &match self.format {
+                    ExportFormat::Txt => format!("{}\n\n", text),
+                    ExportFormat::Md => format!("{} {}\n\n", "#".repeat(*level as usize), text),
+                    ExportFormat::Html => format!("<h{}>{}</h{}>\n", level, text, level),
+                };
+            }
+            Block::ListItem(number, inline) => {
+                if self.list.is_none() && self.format == ExportFormat::Html {
+                    self.output += if number.is_some() { "<ol>\n" } else { "<ul>\n" };
+                }
+                self.list = ordered;
+
+                let text = targets + &self.inline(inline);
+                self.output += &match (self.format, number) {
+                    (ExportFormat::Html, _) => format!("<li>{}</li>\n", text),
+                    (ExportFormat::Txt, None) => format!("• {}\n", text),
+                    (ExportFormat::Md, None) => format!("- {}\n", text),
+                    (_, Some(number)) => format!("{}. {}\n", number, text),
+                };
+            }
+            Block::Image { path, alt } => {
+                let source = self.image_source(path)?;
+                let alt = alt.unwrap_or_default();
+                self.output += &match self.format {
+                    ExportFormat::Txt => format!("[Image: {}] ({})\n\n", alt, source),
+                    ExportFormat::Md => {
+                        format!("{}![{}]({})\n\n", targets, escape_markdown(alt), source)
+                    }
+                    ExportFormat::Html => format!(
+                        "<p>{}<img src=\"{}\" alt=\"{}\"></p>\n",
+                        targets,
+                        escape_html(&source),
+                        escape_html(alt)
+                    ),
+                };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Export the pages in reading order. The images are referenced by their path in the book, or
+// extracted into images_dir and referenced there.
+pub fn export(
+    book: &Book,
+    pages: &[String],
+    format: ExportFormat,
+    images_dir: Option<&Path>,
+) -> io::Result<String> {
+    let mut exporter = Exporter {
+        book,
+        pages,
+        format,
+        images_dir,
+        output: String::new(),
+        list: None,
+    };
+
+    if format == ExportFormat::Html {
+        exporter.output += &format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n",
+            escape_html(&book.title)
+        );
+    }
+
+    for page in pages {
+        if format == ExportFormat::Html {
+            exporter.output +=
+                &format!("<section id=\"{}\">\n", escape_html(&anchor_id(page, None)));
+        } else if format == ExportFormat::Md {
+            // The links to the page point to its start
+            exporter.output +=
+                &format!("<a id=\"{}\"></a>\n\n", escape_html(&anchor_id(page, None)));
+        }
+
+        let current_page = book.pages.get(page).unwrap();
+        for (ids, block) in blocks(&current_page.content, &current_page.anchors) {
+            exporter.block(&ids, page, &block)?;
+        }
+        exporter.end_list();
+
+        if format == ExportFormat::Html {
+            exporter.output += "</section>\n";
+        }
+    }
+
+    if format == ExportFormat::Html {
+        exporter.output += "</body>\n</html>\n";
+    }
+
+    Ok(exporter.output)
+}
@@ -0,0 +1,116 @@
+// The score of a matched character, with the bonuses for a character right after the previous
+// match and for the start of a word. Every character skipped between two matches lowers the score.
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 12;
+const WORD_START: i64 = 8;
+const GAP: i64 = 1;
+
+// Match the query against the text like the file finders of editors, the characters of the query
+// have to be in the text in the same order, but not next to each other. Returns the score and the
+// indices of the matched characters of the text, or None if it does not match. Case is ignored.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let text: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = text.iter().map(|x| fold(*x)).collect();
+
+    if query.is_empty() {
+        return Some((0, vec![]));
+    }
+
+    // The end of the first match
+    let mut matched = 0;
+    let end = folded.iter().position(|x| {
+        if *x == query[matched] {
+            matched += 1;
+        }
+        matched == query.len()
+    })?;
+
+    // Going back from the end finds the shortest match that ends there, which is more likely to
+    // be the word that was meant
+    let mut matched = query.len();
+    let start = (0..=end).rev().find(|x| {
+        if folded[*x] == query[matched - 1] {
+            matched -= 1;
+        }
+        matched == 0
+    })?;
+
+    // The characters are matched from the start again, which keeps the runs of consecutive
+    // characters together
+    let mut indices = vec![];
+    for (index, ch) in folded.iter().enumerate().take(end + 1).skip(start) {
+        if indices.len() < query.len() && *ch == query[indices.len()] {
+            indices.push(index);
+        }
+    }
+
+    let mut score = 0;
+    for (n, index) in indices.iter().enumerate() {
+        score += MATCH;
+        if n > 0 {
+            let gap = index - indices[n - 1] - 1;
+            score += if gap == 0 {
+                CONSECUTIVE
+            } else {
+                -GAP * gap as i64
+            };
+        }
+        if *index == 0 || !text[index - 1].is_alphanumeric() {
+            score += WORD_START;
+        }
+    }
+
+    Some((score, indices))
+}
+
+fn fold(ch: char) -> char {
+    ch.to_lowercase().next().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(query: &str, text: &str) -> Option<Vec<usize>> {
+        fuzzy_match(query, text).map(|x| x.1)
+    }
+
+    fn score(query: &str, text: &str) -> i64 {
+        fuzzy_match(query, text).unwrap().0
+    }
+
+    #[test]
+    fn matches_in_order() {
+        assert_eq!(indices("", "anything"), Some(vec![]));
+        assert_eq!(indices("abc", "a_b_c"), Some(vec![0, 2, 4]));
+        assert_eq!(indices("ba", "ab"), None);
+        assert_eq!(indices("abcd", "abc"), None);
+        assert_eq!(indices("a", ""), None);
+    }
+
+    #[test]
+    fn ignores_case() {
+        assert_eq!(indices("CH", "chapter"), Some(vec![0, 1]));
+        assert_eq!(indices("été", "Été"), Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn finds_the_shortest_match() {
+        // The first match ends at the "b" of "ab", and the shortest match ending there is "ab"
+        assert_eq!(indices("ab", "a xx ab"), Some(vec![5, 6]));
+        // The indices are of the characters, not the bytes
+        assert_eq!(indices("ö", "äö"), Some(vec![1]));
+    }
+
+    #[test]
+    fn scores_runs_and_word_starts() {
+        assert_eq!(score("", "anything"), 0);
+        // Each skipped character costs a point
+        assert_eq!(score("ac", "abc"), 2 * MATCH + WORD_START - GAP);
+        assert_eq!(score("ac", "abbc"), 2 * MATCH + WORD_START - 2 * GAP);
+        // Consecutive characters at the start of a word are the best match
+        assert!(score("one", "Chapter One") > score("one", "Phones"));
+        assert!(score("ch", "Chapter") > score("ch", "Cash"));
+    }
+}
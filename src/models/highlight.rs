@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+};
+
+use super::{book::Book, cache::cache_file};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum HighlightColor {
+    Yellow,
+    Green,
+    Blue,
+    Pink,
+}
+
+impl HighlightColor {
+    pub fn next(self) -> Self {
+        match self {
+            HighlightColor::Yellow => HighlightColor::Green,
+            HighlightColor::Green => HighlightColor::Blue,
+            HighlightColor::Blue => HighlightColor::Pink,
+            HighlightColor::Pink => HighlightColor::Yellow,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HighlightColor::Yellow => "Yellow",
+            HighlightColor::Green => "Green",
+            HighlightColor::Blue => "Blue",
+            HighlightColor::Pink => "Pink",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Highlight {
+    pub page: String,
+    // The range in characters in the text of the page, see Page::text
+    pub start: usize,
+    pub end: usize,
+    pub color: HighlightColor,
+    pub note: Option<String>,
+    // The highlighted text, kept for the export
+    pub text: String,
+}
+
+// The highlights of a book, saved next to its reading position
+#[derive(Serialize, Deserialize, Default)]
+pub struct Highlights {
+    pub highlights: Vec<Highlight>,
+}
+
+impl Highlights {
+    pub fn save(&self, book_hash: &str) -> io::Result<()> {
+        let highlights_file = cache_file(&format!("{}.highlights.json", book_hash))?;
+
+        let file = File::create(highlights_file)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self)?;
+
+        Ok(())
+    }
+
+    pub fn load(book_hash: &str) -> io::Result<Self> {
+        let highlights_file = cache_file(&format!("{}.highlights.json", book_hash))?;
+
+        if !highlights_file.exists() {
+            return Ok(Highlights::default());
+        }
+
+        let file = File::open(highlights_file)?;
+        let reader = BufReader::new(file);
+        let highlights = serde_json::from_reader(reader)?;
+
+        Ok(highlights)
+    }
+
+    // All the highlights and notes as Markdown, grouped by chapter in reading order
+    pub fn to_markdown(&self, book: &Book) -> String {
+        let mut markdown = format!("# {}\n", book.title);
+
+        for page in &book.order {
+            let mut highlights: Vec<&Highlight> =
+                self.highlights.iter().filter(|x| x.page == *page).collect();
+            if highlights.is_empty() {
+                continue;
+            }
+            highlights.sort_by_key(|x| x.start);
+
+            markdown += &format!("\n## {}\n", book.pages.get(page).unwrap().title);
+
+            for highlight in highlights {
+                markdown += "\n";
+                for line in highlight.text.lines() {
+                    markdown += &format!("> {}\n", line);
+                }
+
+                if let Some(note) = &highlight.note {
+                    markdown += &format!("\n{}\n", note);
+                }
+            }
+        }
+
+        markdown
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{book::Book, cache::cache_file};
+
+const HISTORY_FILE: &str = "history.json";
+
+// The size in pixels that the covers are kept at for the library
+const THUMBNAIL_SIZE: (u32, u32) = (300, 450);
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub path: String,
+    // The id that the states of the book are saved with, see book_id
+    pub id: String,
+    pub title: String,
+    pub author: Option<String>,
+    // How far through the book, from 0 to 1
+    pub progress: f64,
+    // Seconds since the Unix epoch
+    pub last_opened: u64,
+}
+
+impl HistoryEntry {
+    // The cover of the book, saved when the book was opened
+    pub fn thumbnail(&self) -> Option<PathBuf> {
+        cache_file(&format!("{}.cover.png", self.id))
+            .ok()
+            .filter(|x| x.exists())
+    }
+
+    // The date that the book was last opened, in UTC
+    pub fn last_opened_date(&self) -> String {
+        let (year, month, day) = civil_date(self.last_opened / 86400);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum HistorySort {
+    Recent,
+    Title,
+    Author,
+    Progress,
+}
+
+impl HistorySort {
+    pub fn next(self) -> Self {
+        match self {
+            HistorySort::Recent => HistorySort::Title,
+            HistorySort::Title => HistorySort::Author,
+            HistorySort::Author => HistorySort::Progress,
+            HistorySort::Progress => HistorySort::Recent,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HistorySort::Recent => "Recent",
+            HistorySort::Title => "Title",
+            HistorySort::Author => "Author",
+            HistorySort::Progress => "Progress",
+        }
+    }
+}
+
+// The books that were opened, most recent first
+#[derive(Serialize, Deserialize, Default)]
+pub struct History {
+    pub books: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn load() -> Self {
+        cache_file(HISTORY_FILE)
+            .and_then(File::open)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let file = File::create(cache_file(HISTORY_FILE)?)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self)?;
+
+        Ok(())
+    }
+
+    // Move the book to the top of the history, and keep its cover for the library
+    pub fn open(&mut self, path: &str, id: &str, book: &Book) {
+        // The books are opened from the library whatever the working directory is
+        let path = fs::canonicalize(path)
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|_| path.to_string());
+
+        let progress = self.get(id).map(|x| x.progress).unwrap_or(0.0);
+        self.books.retain(|x| x.id != id && x.path != path);
+
+        let last_opened = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        self.books.insert(
+            0,
+            HistoryEntry {
+                path,
+                id: id.to_string(),
+                title: book.title.clone(),
+                author: book.author.clone(),
+                progress,
+                last_opened,
+            },
+        );
+
+        if let Some(cover) = book.cover.as_ref().and_then(|x| book.images.get(x)) {
+            if let Ok(file) = cache_file(&format!("{}.cover.png", id)) {
+                cover.decode(THUMBNAIL_SIZE).save(file).ok();
+            }
+        }
+    }
+
+    // The books whose title or author contains the filter, ignoring case
+    pub fn entries(&self, filter: &str, sort: HistorySort) -> Vec<&HistoryEntry> {
+        let filter = filter.to_lowercase();
+        let mut entries: Vec<&HistoryEntry> = self
+            .books
+            .iter()
+            .filter(|x| {
+                x.title.to_lowercase().contains(&filter)
+                    || x.author
+                        .as_ref()
+                        .is_some_and(|x| x.to_lowercase().contains(&filter))
+            })
+            .collect();
+
+        match sort {
+            HistorySort::Recent => (),
+            HistorySort::Title => entries.sort_by_key(|x| x.title.to_lowercase()),
+            // The books without an author are listed last
+            HistorySort::Author => entries.sort_by_key(|x| {
+                (
+                    x.author.is_none(),
+                    x.author.as_ref().map(|x| x.to_lowercase()),
+                )
+            }),
+            HistorySort::Progress => entries.sort_by(|a, b| b.progress.total_cmp(&a.progress)),
+        }
+
+        entries
+    }
+
+    pub fn get(&self, id: &str) -> Option<&HistoryEntry> {
+        self.books.iter().find(|x| x.id == id)
+    }
+
+    pub fn set_progress(&mut self, id: &str, progress: f64) {
+        if let Some(entry) = self.books.iter_mut().find(|x| x.id == id) {
+            entry.progress = progress;
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.books.retain(|x| x.id != id);
+    }
+}
+
+// The year, month and day of the days since the Unix epoch, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
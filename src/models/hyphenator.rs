@@ -0,0 +1,51 @@
+use hyphenation::{Hyphenator as _, Language, Load, Standard};
+use std::collections::HashMap;
+
+// Hyphenates words with the Liang patterns bundled for each language, which are loaded when they
+// are first used
+#[derive(Default)]
+pub struct Hyphenator {
+    dictionaries: HashMap<Language, Option<Standard>>,
+}
+
+impl Hyphenator {
+    // The indices of the characters of the word that it can be broken before, with a hyphen.
+    // Words of languages without patterns are not hyphenated.
+    pub fn breaks(&mut self, word: &str, lang: &str) -> Vec<usize> {
+        let Some(language) = language(lang) else {
+            return vec![];
+        };
+        let dictionary = self
+            .dictionaries
+            .entry(language)
+            .or_insert_with(|| Standard::from_embedded(language).ok());
+        let Some(dictionary) = dictionary else {
+            return vec![];
+        };
+
+        // The breaks are byte indices
+        dictionary
+            .hyphenate(word)
+            .breaks
+            .iter()
+            .map(|x| word[..*x].chars().count())
+            .collect()
+    }
+}
+
+// The language of a tag like "en", "en-GB" or "de-DE", the tags without a region use the most
+// common patterns of the language
+fn language(tag: &str) -> Option<Language> {
+    let tag = tag.trim().to_lowercase().replace('_', "-");
+    let primary = tag.split('-').next().unwrap();
+
+    Language::try_from_code(&tag).or(match primary {
+        "en" => Some(Language::EnglishUS),
+        "de" => Some(Language::German1996),
+        "el" => Some(Language::GreekMono),
+        "mn" => Some(Language::Mongolian),
+        "sr" => Some(Language::SerbianCyrillic),
+        "no" => Some(Language::NorwegianBokmal),
+        _ => Language::try_from_code(primary),
+    })
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read},
+};
+
+use super::cache::cache_file;
+
+const IDENTITIES_FILE: &str = "identities.json";
+
+// The books are read in chunks of this size when they are hashed
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+// The ids of the saved states of the books, looked up by the identifier of the book and the hash of
+// its content. A book that changed in one of them is still linked to its state through the other.
+#[derive(Serialize, Deserialize, Default)]
+struct Identities {
+    identifiers: HashMap<String, String>,
+    hashes: HashMap<String, String>,
+}
+
+impl Identities {
+    // A missing file is an empty table, but a file that cannot be read is an error, so that it is
+    // not saved over
+    fn load() -> io::Result<Self> {
+        let file = match File::open(cache_file(IDENTITIES_FILE)?) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Identities::default()),
+            Err(err) => return Err(err),
+        };
+
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let file = File::create(cache_file(IDENTITIES_FILE)?)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self)?;
+
+        Ok(())
+    }
+}
+
+// The MD5 hash of the file, read in chunks instead of all at once
+pub fn content_hash(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.consume(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", context.compute()))
+}
+
+// The id that the reading position, the bookmarks and the highlights of the book are saved with
+pub fn book_id(path: &str, identifier: Option<&str>) -> io::Result<String> {
+    let hash = content_hash(path)?;
+
+    let mut identities = Identities::load()?;
+    let id = identifier
+        .and_then(|x| identities.identifiers.get(x))
+        .or_else(|| identities.hashes.get(&hash))
+        .cloned()
+        // The older versions saved the states with the hash of the content
+        .unwrap_or_else(|| hash.clone());
+
+    // Link the current identifier and hash to the id, so that the book is found by them
+    let mut changed = false;
+    let mut link = |table: &mut HashMap<String, String>, key: String| {
+        changed |= table.insert(key, id.clone()).as_ref() != Some(&id);
+    };
+    if let Some(identifier) = identifier {
+        link(&mut identities.identifiers, identifier.to_string());
+    }
+    link(&mut identities.hashes, hash);
+
+    if changed {
+        identities.save()?;
+    }
+
+    Ok(id)
+}
@@ -1,42 +1,123 @@
-use image::{load_from_memory, DynamicImage, GenericImageView};
+use std::{
+    io::Cursor,
+    sync::{Arc, OnceLock},
+};
+
+use image::{imageops::FilterType, load_from_memory, DynamicImage, ImageReader, RgbaImage};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{self, fontdb::Database},
+};
 
 pub struct Image {
     content: Vec<u8>,
-    parsed: Option<DynamicImage>,
+    svg: bool,
     width: Option<u32>,
     height: Option<u32>,
 }
 
 impl Image {
-    pub fn new(content: Vec<u8>) -> Image {
+    pub fn new(content: Vec<u8>, media_type: &str) -> Image {
         Image {
             content,
-            parsed: None,
+            svg: media_type == "image/svg+xml",
             width: None,
             height: None,
         }
     }
 
-    pub fn get(&mut self) -> &DynamicImage {
-        if self.parsed.is_none() {
-            self.parsed = Some(load_from_memory(&self.content).unwrap());
-            self.content.clear();
+    // The fonts are loaded only once, as this is slow
+    fn fontdb() -> Arc<Database> {
+        static FONTDB: OnceLock<Arc<Database>> = OnceLock::new();
+
+        FONTDB
+            .get_or_init(|| {
+                let mut fontdb = Database::new();
+                fontdb.load_system_fonts();
+                Arc::new(fontdb)
+            })
+            .clone()
+    }
+
+    fn parse_svg(content: &[u8]) -> Option<usvg::Tree> {
+        let options = usvg::Options {
+            fontdb: Image::fontdb(),
+            ..Default::default()
+        };
+
+        usvg::Tree::from_data(content, &options).ok()
+    }
+
+    // Rasterize the SVG so that it fits into max_size
+    fn rasterize_svg(content: &[u8], max_size: (u32, u32)) -> Option<DynamicImage> {
+        let tree = Image::parse_svg(content)?;
+
+        let size = tree.size();
+        let scale = (max_size.0 as f32 / size.width()).min(max_size.1 as f32 / size.height());
+        let width = ((size.width() * scale).round() as u32).max(1);
+        let height = ((size.height() * scale).round() as u32).max(1);
+
+        let mut pixmap = Pixmap::new(width, height)?;
+        resvg::render(
+            &tree,
+            Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+
+        // The pixmap is premultiplied, but the image crate expects straight alpha
+        let mut buffer = Vec::with_capacity((width * height * 4) as usize);
+        for pixel in pixmap.pixels() {
+            let color = pixel.demultiply();
+            buffer.extend([color.red(), color.green(), color.blue(), color.alpha()]);
         }
 
-        let dem = self.parsed.as_ref().unwrap().dimensions();
+        RgbaImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8)
+    }
+
+    // The encoded image, as it is stored in the book
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
 
-        self.width = Some(dem.0);
-        self.height = Some(dem.1);
+    // The native size in pixels of the image, read without decoding the whole image
+    pub fn dimensions(&mut self) -> (u32, u32) {
+        if self.width.is_none() || self.height.is_none() {
+            let dimensions = if self.svg {
+                Image::parse_svg(&self.content).map(|tree| {
+                    let size = tree.size().to_int_size();
+                    (size.width(), size.height())
+                })
+            } else {
+                ImageReader::new(Cursor::new(&self.content))
+                    .with_guessed_format()
+                    .ok()
+                    .and_then(|reader| reader.into_dimensions().ok())
+            };
+            let (width, height) = dimensions.unwrap_or((1, 1));
+
+            self.width = Some(width);
+            self.height = Some(height);
+        }
 
-        self.parsed.as_ref().unwrap()
+        (self.width.unwrap(), self.height.unwrap())
     }
 
-    // FIXME temp fix only
-    pub fn cal_width(&self, height: usize) -> u16 {
-        return if self.width.unwrap() > self.height.unwrap() {
-            (self.width.unwrap() * (height as u32) / self.height.unwrap() / 2) as u16
-        } else {
-            (self.width.unwrap() * (height as u32) / self.height.unwrap()) as u16
+    // Decode the image, downscaled so that it is not larger than max_size
+    pub fn decode(&self, max_size: (u32, u32)) -> DynamicImage {
+        if self.svg {
+            // Show an empty image instead of crashing if the SVG is invalid
+            return Image::rasterize_svg(&self.content, max_size)
+                .unwrap_or_else(|| DynamicImage::new_rgba8(1, 1));
+        }
+
+        // Show an empty image instead of crashing if the image is corrupt
+        let Ok(image) = load_from_memory(&self.content) else {
+            return DynamicImage::new_rgba8(1, 1);
         };
+        if image.width() > max_size.0 || image.height() > max_size.1 {
+            image.resize(max_size.0, max_size.1, FilterType::Triangle)
+        } else {
+            image
+        }
     }
 }
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use image::DynamicImage;
+
+use super::image::Image;
+
+// A least recently used cache, which evicts entries to keep their total size within a budget
+pub struct MemoryCache<V> {
+    // Value is the cached value, its size in bytes and when it was last used
+    entries: HashMap<String, (V, usize, u64)>,
+    budget: usize,
+    used: usize,
+    clock: u64,
+}
+
+impl<V> MemoryCache<V> {
+    pub fn new(budget: usize) -> Self {
+        MemoryCache {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        self.entries.get_mut(key).map(|entry| {
+            entry.2 = clock;
+            &mut entry.0
+        })
+    }
+
+    pub fn insert(&mut self, key: String, value: V, size: usize) {
+        self.remove(&key);
+
+        // Evict the least recently used entries until the new entry fits
+        while self.used + size > self.budget && !self.entries.is_empty() {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.2)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            self.remove(&oldest);
+        }
+
+        self.clock += 1;
+        self.used += size;
+        self.entries.insert(key, (value, size, self.clock));
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some((_, size, _)) = self.entries.remove(key) {
+            self.used -= size;
+        }
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+        let removed: Vec<String> = self.entries.keys().filter(|key| !f(key)).cloned().collect();
+
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+}
+
+// Decoded images, keyed by the path of the image
+pub struct DecodedImages {
+    // The size is the resolution that the image was decoded for
+    cache: MemoryCache<(DynamicImage, (u32, u32))>,
+}
+
+impl DecodedImages {
+    pub fn new(budget: usize) -> Self {
+        DecodedImages {
+            cache: MemoryCache::new(budget),
+        }
+    }
+
+    // max_size is the resolution in pixels that the image will be shown at most
+    pub fn get(
+        &mut self,
+        images: &HashMap<String, Image>,
+        path: &str,
+        max_size: (u32, u32),
+    ) -> &DynamicImage {
+        let cached = self
+            .cache
+            .get_mut(path)
+            .is_some_and(|(_, size)| *size == max_size);
+
+        if !cached {
+            let image = images.get(path).unwrap().decode(max_size);
+            let size = image.as_bytes().len();
+            self.cache.insert(path.to_string(), (image, max_size), size);
+        }
+
+        &self.cache.get_mut(path).unwrap().0
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.cache.remove(path);
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.retain(|_| false);
+    }
+}
+
+// The ids of the image states are made of the path of the image and a variant, as the same image
+// can be shown at different sizes
+pub fn image_id(path: &str, variant: &str) -> String {
+    format!("{}@{}", path, variant)
+}
+
+pub fn image_id_parts(id: &str) -> (&str, &str) {
+    id.rsplit_once('@').unwrap_or((id, ""))
+}
@@ -0,0 +1,515 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{de, Deserialize, Deserializer};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    ReloadConfig,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    PreviousChapter,
+    NextChapter,
+    BookInfo,
+    ViewImage,
+    ChooseImage,
+    SwitchMode,
+    SwitchTheme,
+    Narrower,
+    Wider,
+    DecreaseMargin,
+    IncreaseMargin,
+    ParagraphSpacing,
+    Indent,
+    Justify,
+    Hyphenate,
+    Select,
+    AddBookmark,
+    Bookmarks,
+    Search,
+    NextMatch,
+    PreviousMatch,
+    ClearSearch,
+    NextWord,
+    PreviousWord,
+    NextLine,
+    PreviousLine,
+    StartSelection,
+    Delete,
+    Zoom,
+    NextImage,
+    PreviousImage,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    Sort,
+    Library,
+}
+
+// The screens that the keys are bound on, a key can be bound to one action on each of them
+#[derive(Clone, Copy, PartialEq)]
+pub enum Context {
+    Reading,
+    Selection,
+    Viewer,
+    Bookmarks,
+    Info,
+    Library,
+}
+
+const CONTEXTS: [Context; 6] = [
+    Context::Reading,
+    Context::Selection,
+    Context::Viewer,
+    Context::Bookmarks,
+    Context::Info,
+    Context::Library,
+];
+
+impl Action {
+    // The screens that the action can be used on
+    fn contexts(self) -> &'static [Context] {
+        match self {
+            Action::Quit | Action::ReloadConfig | Action::SwitchTheme => &[
+                Context::Reading,
+                Context::Viewer,
+                Context::Bookmarks,
+                Context::Info,
+                Context::Library,
+            ],
+            Action::ScrollUp | Action::ScrollDown => &[
+                Context::Reading,
+                Context::Bookmarks,
+                Context::Info,
+                Context::Library,
+            ],
+            Action::PageUp | Action::PageDown => &[Context::Reading, Context::Info],
+            Action::BookInfo => &[Context::Reading, Context::Info],
+            Action::ViewImage => &[Context::Reading, Context::Viewer],
+            Action::Bookmarks => &[Context::Reading, Context::Bookmarks],
+            Action::Search => &[Context::Reading, Context::Info, Context::Library],
+            Action::PreviousChapter
+            | Action::NextChapter
+            | Action::ChooseImage
+            | Action::SwitchMode
+            | Action::Narrower
+            | Action::Wider
+            | Action::DecreaseMargin
+            | Action::IncreaseMargin
+            | Action::ParagraphSpacing
+            | Action::Indent
+            | Action::Justify
+            | Action::Hyphenate
+            | Action::Select
+            | Action::AddBookmark
+            | Action::NextMatch
+            | Action::PreviousMatch
+            | Action::ClearSearch => &[Context::Reading],
+            Action::NextWord
+            | Action::PreviousWord
+            | Action::NextLine
+            | Action::PreviousLine
+            | Action::StartSelection => &[Context::Selection],
+            Action::Delete => &[Context::Selection, Context::Bookmarks, Context::Library],
+            Action::Zoom
+            | Action::NextImage
+            | Action::PreviousImage
+            | Action::PanLeft
+            | Action::PanRight
+            | Action::PanUp
+            | Action::PanDown => &[Context::Viewer],
+            Action::Sort => &[Context::Library],
+            Action::Library => &[Context::Info],
+        }
+    }
+}
+
+// A key with the Ctrl and Alt modifiers, written like "j", "ctrl-r" or "pagedown" in the config
+#[derive(Clone, PartialEq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    fn parse(value: &str) -> Result<KeyBinding, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = value;
+        loop {
+            if let Some(rest) = key.strip_prefix("ctrl-") {
+                modifiers |= KeyModifiers::CONTROL;
+                key = rest;
+            } else if let Some(rest) = key.strip_prefix("alt-") {
+                modifiers |= KeyModifiers::ALT;
+                key = rest;
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "space" => KeyCode::Char(' '),
+                name => match name.strip_prefix('f').and_then(|x| x.parse().ok()) {
+                    Some(number @ 1..=12) => KeyCode::F(number),
+                    _ => return Err(format!("invalid key '{}'", value)),
+                },
+            },
+        };
+
+        Ok(KeyBinding { code, modifiers })
+    }
+
+    fn matches(&self, key: &KeyEvent) -> bool {
+        // Shift is part of the character, like 'K'
+        key.code == self.code
+            && key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT) == self.modifiers
+    }
+
+    // The key as it is shown in the instructions
+    pub fn label(&self) -> String {
+        let key = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::F(number) => format!("F{}", number),
+            code => format!("{:?}", code),
+        };
+
+        let mut label = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            label += "Ctrl+";
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            label += "Alt+";
+        }
+
+        label + &key
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        KeyBinding::parse(&value).map_err(de::Error::custom)
+    }
+}
+
+// The keys of each action, an action can have several keys
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keymap {
+    quit: Vec<KeyBinding>,
+    reload_config: Vec<KeyBinding>,
+    scroll_up: Vec<KeyBinding>,
+    scroll_down: Vec<KeyBinding>,
+    page_up: Vec<KeyBinding>,
+    page_down: Vec<KeyBinding>,
+    previous_chapter: Vec<KeyBinding>,
+    next_chapter: Vec<KeyBinding>,
+    book_info: Vec<KeyBinding>,
+    view_image: Vec<KeyBinding>,
+    choose_image: Vec<KeyBinding>,
+    switch_mode: Vec<KeyBinding>,
+    switch_theme: Vec<KeyBinding>,
+    narrower: Vec<KeyBinding>,
+    wider: Vec<KeyBinding>,
+    decrease_margin: Vec<KeyBinding>,
+    increase_margin: Vec<KeyBinding>,
+    paragraph_spacing: Vec<KeyBinding>,
+    indent: Vec<KeyBinding>,
+    justify: Vec<KeyBinding>,
+    hyphenate: Vec<KeyBinding>,
+    select: Vec<KeyBinding>,
+    add_bookmark: Vec<KeyBinding>,
+    bookmarks: Vec<KeyBinding>,
+    search: Vec<KeyBinding>,
+    next_match: Vec<KeyBinding>,
+    previous_match: Vec<KeyBinding>,
+    clear_search: Vec<KeyBinding>,
+    next_word: Vec<KeyBinding>,
+    previous_word: Vec<KeyBinding>,
+    next_line: Vec<KeyBinding>,
+    previous_line: Vec<KeyBinding>,
+    start_selection: Vec<KeyBinding>,
+    delete: Vec<KeyBinding>,
+    zoom: Vec<KeyBinding>,
+    next_image: Vec<KeyBinding>,
+    previous_image: Vec<KeyBinding>,
+    pan_left: Vec<KeyBinding>,
+    pan_right: Vec<KeyBinding>,
+    pan_up: Vec<KeyBinding>,
+    pan_down: Vec<KeyBinding>,
+    sort: Vec<KeyBinding>,
+    library: Vec<KeyBinding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|x| KeyBinding::parse(x).unwrap()).collect();
+
+        Keymap {
+            quit: keys(&["Q", "q"]),
+            reload_config: keys(&["ctrl-r"]),
+            scroll_up: keys(&["up", "k", "K"]),
+            scroll_down: keys(&["down", "j", "J"]),
+            page_up: keys(&["pageup"]),
+            page_down: keys(&["space", "pagedown"]),
+            previous_chapter: keys(&["left", "h", "H"]),
+            next_chapter: keys(&["right", "l", "L"]),
+            book_info: keys(&["I", "i"]),
+            view_image: keys(&["V", "v"]),
+            choose_image: keys(&["tab"]),
+            switch_mode: keys(&["M", "m"]),
+            switch_theme: keys(&["T", "t"]),
+            narrower: keys(&["["]),
+            wider: keys(&["]"]),
+            decrease_margin: keys(&["{"]),
+            increase_margin: keys(&["}"]),
+            paragraph_spacing: keys(&["p"]),
+            indent: keys(&["f"]),
+            justify: keys(&["="]),
+            hyphenate: keys(&["-"]),
+            select: keys(&["S", "s"]),
+            add_bookmark: keys(&["b"]),
+            bookmarks: keys(&["B"]),
+            search: keys(&["/"]),
+            next_match: keys(&["n"]),
+            previous_match: keys(&["N"]),
+            clear_search: keys(&["esc"]),
+            next_word: keys(&["right", "l", "L"]),
+            previous_word: keys(&["left", "h", "H"]),
+            next_line: keys(&["down", "j", "J"]),
+            previous_line: keys(&["up", "k", "K"]),
+            start_selection: keys(&["space", "v", "V"]),
+            delete: keys(&["d", "D", "delete"]),
+            zoom: keys(&["z", "Z"]),
+            next_image: keys(&["n", "N", "pagedown"]),
+            previous_image: keys(&["p", "P", "pageup"]),
+            pan_left: keys(&["left", "h", "H"]),
+            pan_right: keys(&["right", "l", "L"]),
+            pan_up: keys(&["up", "k", "K"]),
+            pan_down: keys(&["down", "j", "J"]),
+            sort: keys(&["o", "O"]),
+            library: keys(&["L", "l"]),
+        }
+    }
+}
+
+impl Keymap {
+    fn bindings(&self) -> [(Action, &'static str, &Vec<KeyBinding>); 43] {
+        [
+            (Action::Quit, "quit", &self.quit),
+            (Action::ReloadConfig, "reload_config", &self.reload_config),
+            (Action::ScrollUp, "scroll_up", &self.scroll_up),
+            (Action::ScrollDown, "scroll_down", &self.scroll_down),
+            (Action::PageUp, "page_up", &self.page_up),
+            (Action::PageDown, "page_down", &self.page_down),
+            (
+                Action::PreviousChapter,
+                "previous_chapter",
+                &self.previous_chapter,
+            ),
+            (Action::NextChapter, "next_chapter", &self.next_chapter),
+            (Action::BookInfo, "book_info", &self.book_info),
+            (Action::ViewImage, "view_image", &self.view_image),
+            (Action::ChooseImage, "choose_image", &self.choose_image),
+            (Action::SwitchMode, "switch_mode", &self.switch_mode),
+            (Action::SwitchTheme, "switch_theme", &self.switch_theme),
+            (Action::Narrower, "narrower", &self.narrower),
+            (Action::Wider, "wider", &self.wider),
+            (
+                Action::DecreaseMargin,
+                "decrease_margin",
+                &self.decrease_margin,
+            ),
+            (
+                Action::IncreaseMargin,
+                "increase_margin",
+                &self.increase_margin,
+            ),
+            (
+                Action::ParagraphSpacing,
+                "paragraph_spacing",
+                &self.paragraph_spacing,
+            ),
+            (Action::Indent, "indent", &self.indent),
+            (Action::Justify, "justify", &self.justify),
+            (Action::Hyphenate, "hyphenate", &self.hyphenate),
+            (Action::Select, "select", &self.select),
+            (Action::AddBookmark, "add_bookmark", &self.add_bookmark),
+            (Action::Bookmarks, "bookmarks", &self.bookmarks),
+            (Action::Search, "search", &self.search),
+            (Action::NextMatch, "next_match", &self.next_match),
+            (
+                Action::PreviousMatch,
+                "previous_match",
+                &self.previous_match,
+            ),
+            (Action::ClearSearch, "clear_search", &self.clear_search),
+            (Action::NextWord, "next_word", &self.next_word),
+            (Action::PreviousWord, "previous_word", &self.previous_word),
+            (Action::NextLine, "next_line", &self.next_line),
+            (Action::PreviousLine, "previous_line", &self.previous_line),
+            (
+                Action::StartSelection,
+                "start_selection",
+                &self.start_selection,
+            ),
+            (Action::Delete, "delete", &self.delete),
+            (Action::Zoom, "zoom", &self.zoom),
+            (Action::NextImage, "next_image", &self.next_image),
+            (
+                Action::PreviousImage,
+                "previous_image",
+                &self.previous_image,
+            ),
+            (Action::PanLeft, "pan_left", &self.pan_left),
+            (Action::PanRight, "pan_right", &self.pan_right),
+            (Action::PanUp, "pan_up", &self.pan_up),
+            (Action::PanDown, "pan_down", &self.pan_down),
+            (Action::Sort, "sort", &self.sort),
+            (Action::Library, "library", &self.library),
+        ]
+    }
+
+    // A key can only be bound to one action on each screen
+    pub fn validate(&self) -> Result<(), String> {
+        for context in CONTEXTS {
+            let bindings: Vec<_> = self
+                .bindings()
+                .into_iter()
+                .filter(|(action, _, _)| action.contexts().contains(&context))
+                .collect();
+
+            for (index, (_, name, keys)) in bindings.iter().enumerate() {
+                for key in keys.iter() {
+                    if let Some((_, other, _)) = bindings[index + 1..]
+                        .iter()
+                        .find(|(_, _, x)| x.contains(key))
+                    {
+                        return Err(format!(
+                            "key '{}' is bound to both {} and {}",
+                            key.label(),
+                            name,
+                            other
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The action of the key on the screen
+    pub fn action(&self, key: &KeyEvent, context: Context) -> Option<Action> {
+        self.bindings()
+            .into_iter()
+            .filter(|(action, _, _)| action.contexts().contains(&context))
+            .find(|(_, _, keys)| keys.iter().any(|x| x.matches(key)))
+            .map(|(action, _, _)| action)
+    }
+
+    // The first key of the action, as it is shown in the instructions
+    pub fn label(&self, action: Action) -> String {
+        self.bindings()
+            .into_iter()
+            .find(|(x, _, _)| *x == action)
+            .and_then(|(_, _, keys)| keys.first())
+            .map(|x| x.label())
+            .unwrap_or("Unbound".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyBinding {
+        KeyBinding { code, modifiers }
+    }
+
+    fn keymap(config: &str) -> Keymap {
+        toml::from_str(config).unwrap()
+    }
+
+    #[test]
+    fn parse_keys() {
+        let parse = |x| KeyBinding::parse(x).unwrap();
+
+        assert!(parse("j") == key(KeyCode::Char('j'), KeyModifiers::NONE));
+        assert!(parse("J") == key(KeyCode::Char('J'), KeyModifiers::NONE));
+        assert!(parse("space") == key(KeyCode::Char(' '), KeyModifiers::NONE));
+        assert!(parse("PageDown") == key(KeyCode::PageDown, KeyModifiers::NONE));
+        assert!(parse("f1") == key(KeyCode::F(1), KeyModifiers::NONE));
+        assert!(parse("F12") == key(KeyCode::F(12), KeyModifiers::NONE));
+        assert!(parse("ctrl-r") == key(KeyCode::Char('r'), KeyModifiers::CONTROL));
+        assert!(
+            parse("alt-ctrl-left") == key(KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::ALT)
+        );
+        // The prefixes are only modifiers before a key
+        assert!(parse("-") == key(KeyCode::Char('-'), KeyModifiers::NONE));
+        assert!(parse("ctrl--") == key(KeyCode::Char('-'), KeyModifiers::CONTROL));
+    }
+
+    #[test]
+    fn parse_invalid_keys() {
+        for value in ["", "ctrl-", "f0", "f13", "pgdn", "jk"] {
+            assert!(KeyBinding::parse(value).is_err(), "'{}' was parsed", value);
+        }
+    }
+
+    #[test]
+    fn matches_ignore_shift() {
+        let binding = KeyBinding::parse("K").unwrap();
+        assert!(binding.matches(&KeyEvent::new(KeyCode::Char('K'), KeyModifiers::SHIFT)));
+        assert!(!binding.matches(&KeyEvent::new(KeyCode::Char('K'), KeyModifiers::CONTROL)));
+
+        let binding = KeyBinding::parse("ctrl-r").unwrap();
+        assert!(binding.matches(&KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL)));
+        assert!(!binding.matches(&KeyEvent::new(KeyCode::Char('r'), KeyModifiers::NONE)));
+    }
+
+    #[test]
+    fn default_keys_are_valid() {
+        assert!(Keymap::default().validate().is_ok());
+    }
+
+    #[test]
+    fn conflicts_on_a_screen() {
+        // The search and the next chapter are both on the reading screen
+        let err = keymap("search = [\"l\"]").validate().unwrap_err();
+        assert_eq!(err, "key 'l' is bound to both next_chapter and search");
+
+        // Delete is not used on the reading screen, so its keys can be bound there too
+        let keys = keymap("delete = [\"x\"]\nswitch_mode = [\"x\"]");
+        assert!(keys.validate().is_ok());
+        let x = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert!(keys.action(&x, Context::Reading) == Some(Action::SwitchMode));
+        assert!(keys.action(&x, Context::Bookmarks) == Some(Action::Delete));
+        assert!(keys.action(&x, Context::Viewer).is_none());
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        assert!(toml::from_str::<Keymap>("jump = [\"j\"]").is_err());
+        assert!(toml::from_str::<Keymap>("quit = [\"nope\"]").is_err());
+    }
+}
@@ -1,76 +1,304 @@
 use path_clean::PathClean;
-use regex::Regex;
 use roxmltree::{Document, Node, ParsingOptions};
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
+use unicode_bidi::get_base_direction;
 
-pub enum TextStyle {
-    Regular,
-    Bold,
-    Italic,
-    Underline,
+// The styles of the elements that the text is in, so that bold text in italic text is both
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    // The level of the heading, from 1 to 6. The text of a heading is shown as a heading whatever
+    // the other styles are.
+    pub heading: Option<u8>,
+}
+
+impl TextStyle {
+    fn bold(self) -> TextStyle {
+        TextStyle { bold: true, ..self }
+    }
+
+    fn italic(self) -> TextStyle {
+        TextStyle {
+            italic: true,
+            ..self
+        }
+    }
+
+    fn underline(self) -> TextStyle {
+        TextStyle {
+            underline: true,
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+impl Direction {
+    // The direction of the script of a language tag like "ar" or "he-IL"
+    pub fn of_language(tag: &str) -> Direction {
+        let primary = tag.trim().split(['-', '_']).next().unwrap().to_lowercase();
+
+        match primary.as_str() {
+            "ar" | "arc" | "ckb" | "dv" | "fa" | "he" | "iw" | "ps" | "sd" | "syr" | "ug"
+            | "ur" | "yi" => Direction::Rtl,
+            _ => Direction::Ltr,
+        }
+    }
 }
 
 pub enum ContentType {
     LineBreak,
+    // The start of an item of a list, with its number in an ordered list
+    ListItem {
+        number: Option<usize>,
+    },
     Text {
         text: String,
         style: TextStyle,
         hints: Option<String>,
         href: Option<String>,
+        // The language tag of the text, like "en-GB", if the element or its parents have one
+        lang: Option<String>,
+        // The direction of the text, if the element or its parents have a dir attribute
+        dir: Option<Direction>,
     },
-    Image(String),
-    Img(String),
+    Image {
+        path: String,
+        alt: Option<String>,
+    },
+    Img {
+        path: String,
+        alt: Option<String>,
+    },
+    // Where an element with an id starts, which links can point to. It is only used while the page
+    // is parsed, and is moved into Page::anchors.
+    Anchor(String),
 }
 
 pub struct Page {
     pub title: String,
     pub content: Vec<ContentType>,
+    // Inline <svg> elements, the first string is the id used as the image path, and the second
+    // string is the SVG document
+    pub svgs: Vec<(String, String)>,
+    // The elements of Page::content where the elements with the ids start
+    pub anchors: HashMap<String, usize>,
+    // Number of characters of the text of the page, used to weight the progress through the book.
+    // It is in the same unit as the positions in the page, where every element that is not text
+    // counts as one character.
+    pub length: usize,
 }
 
 impl Page {
-    fn parse_image_or_img(node: Node, path: &Path) -> ContentType {
+    // Whether the <svg> only wraps images, like the common cover page pattern
+    fn is_image_wrapper(node: Node) -> bool {
+        let mut has_image = false;
+
+        for ele in node.descendants().skip(1).filter(|n| n.is_element()) {
+            match ele.tag_name().name() {
+                "image" => has_image = true,
+                "title" | "desc" | "g" => (),
+                _ => return false,
+            }
+        }
+
+        has_image
+    }
+
+    fn svg_id(node: Node, path: &Path) -> String {
+        format!("{}#svg-{}", path.to_str().unwrap(), node.range().start)
+    }
+
+    // Extract the <svg> as a standalone document, the namespaces might be declared on the
+    // <html> element instead of the <svg> element
+    fn svg_source(node: Node, input: &str) -> String {
+        let mut source = input[node.range()].to_string();
+        let start_tag = &source[..source.find('>').unwrap_or(source.len())];
+
+        let mut namespaces = String::new();
+        if !start_tag.contains("xmlns=") {
+            namespaces += r#" xmlns="http://www.w3.org/2000/svg""#;
+        }
+        if source.contains("xlink:") && !start_tag.contains("xmlns:xlink") {
+            namespaces += r#" xmlns:xlink="http://www.w3.org/1999/xlink""#;
+        }
+        let name_end = source
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap();
+        source.insert_str(name_end, &namespaces);
+
+        source
+    }
+
+    fn parse_svg(node: Node, path: &Path) -> ContentType {
+        let alt = node
+            .children()
+            .find(|n| n.has_tag_name("title"))
+            .and_then(|n| n.text())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        ContentType::Image {
+            path: Page::svg_id(node, path),
+            alt,
+        }
+    }
+
+    // An image without a source is skipped
+    fn parse_image_or_img(node: Node, path: &Path) -> Option<ContentType> {
         let href = node
             .attributes()
-            .find(|e| e.name() == "href" || e.name() == "src")
-            .unwrap()
+            .find(|e| e.name() == "href" || e.name() == "src")?
             .value();
 
         let rel_path = Path::new(href);
         let root_path = path.parent().unwrap().join(rel_path).clean();
         let string_path = root_path.to_str().unwrap().to_string();
 
-        if node.tag_name().name() == "img" {
-            ContentType::Img(string_path)
+        // Keep the alternative text so the image can be described when it cannot be shown
+        let alt = node
+            .attribute("alt")
+            .or(node.attribute("title"))
+            .map(|v| v.replace("\n", " ").trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        Some(if node.tag_name().name() == "img" {
+            ContentType::Img {
+                path: string_path,
+                alt,
+            }
+        } else {
+            ContentType::Image {
+                path: string_path,
+                alt,
+            }
+        })
+    }
+
+    // Whether the node is text or an element within a line of text
+    fn is_inline(node: Option<Node>) -> bool {
+        node.is_some_and(|n| {
+            n.is_text()
+                || matches!(
+                    n.tag_name().name(),
+                    "a" | "b" | "strong" | "i" | "em" | "u" | "span" | "ruby" | "sub" | "sup"
+                )
+        })
+    }
+
+    // The language of the node, from the closest lang or xml:lang attribute
+    fn language(node: Node) -> Option<String> {
+        node.ancestors()
+            .find_map(|n| {
+                n.attribute(("http://www.w3.org/XML/1998/namespace", "lang"))
+                    .or(n.attribute("lang"))
+            })
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    }
+
+    // The direction of the node, from the closest dir attribute. The direction of "auto" is the
+    // direction of the first strong character of the element.
+    fn direction(node: Node) -> Option<Direction> {
+        let element = node.ancestors().find(|n| n.has_attribute("dir"))?;
+
+        match element
+            .attribute("dir")
+            .unwrap()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "rtl" => Some(Direction::Rtl),
+            "ltr" => Some(Direction::Ltr),
+            _ => {
+                let text: String = element
+                    .descendants()
+                    .filter_map(|n| n.is_text().then(|| n.text()).flatten())
+                    .collect();
+
+                match get_base_direction(text.as_str()) {
+                    unicode_bidi::Direction::Rtl => Some(Direction::Rtl),
+                    unicode_bidi::Direction::Ltr => Some(Direction::Ltr),
+                    unicode_bidi::Direction::Mixed => None,
+                }
+            }
+        }
+    }
+
+    // The first or the last character of the text of the node, without the whitespace
+    fn edge_char(node: Option<Node>, last: bool) -> Option<char> {
+        let text: String = node?
+            .descendants()
+            .filter_map(|n| n.is_text().then(|| n.text()).flatten())
+            .collect();
+        let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+
+        if last {
+            text.chars().last()
         } else {
-            ContentType::Image(string_path)
+            text.chars().next()
         }
     }
 
     fn parse_text(node: Node, style: TextStyle) -> Option<ContentType> {
-        let text = node
-            .text()
-            .unwrap_or_default()
-            .replace("\n", "")
-            .trim()
-            .to_string();
+        let raw = node.text().unwrap_or_default();
+        let trimmed = raw.trim_matches(|c: char| c.is_ascii_whitespace());
+        let mut text = collapse_whitespace(trimmed);
+
+        // Keep a space between the text and the elements next to it, like "<i>a</i> and <b>b</b>"
+        let prev = Page::is_inline(node.prev_sibling());
+        let next = Page::is_inline(node.next_sibling());
+        let before = Page::edge_char(node.prev_sibling(), true);
+        let after = Page::edge_char(node.next_sibling(), false);
+        if text.is_empty() {
+            if !raw.is_empty() && prev && next && is_space(raw, before, after) {
+                text.push(' ');
+            }
+        } else {
+            // The whitespace before and after the text
+            let start = raw.find(|c: char| !c.is_ascii_whitespace()).unwrap();
+            let leading = &raw[..start];
+            let trailing = &raw[start + trimmed.len()..];
+            if !leading.is_empty() && prev && is_space(leading, before, text.chars().next()) {
+                text.insert(0, ' ');
+            }
+            if !trailing.is_empty() && next && is_space(trailing, trimmed.chars().last(), after) {
+                text.push(' ');
+            }
+        }
 
         (!text.is_empty()).then_some(ContentType::Text {
             text,
             style,
             hints: None,
             href: None,
+            lang: Page::language(node),
+            dir: Page::direction(node),
         })
     }
 
-    fn parse_a(node: Node, path: &Path) -> Option<ContentType> {
+    fn parse_a(node: Node, path: &Path, style: TextStyle) -> Option<ContentType> {
         let href = node.attribute("href").unwrap();
 
-        let rel_path = Path::new(href);
-        let root_path = path.parent().unwrap().join(rel_path).clean();
-        let string_path = root_path.to_str().unwrap().to_string();
-
-        let re = Regex::new(r"#.*$").unwrap();
-        let link = re.replace_all(&string_path, "").into_owned();
+        // The link keeps the id of the element that it points to, and a link into the same page
+        // is only the id
+        let (file, id) = href.split_once('#').unwrap_or((href, ""));
+        let root_path = match file {
+            "" => path.to_path_buf(),
+            file => path.parent().unwrap().join(file).clean(),
+        };
+        let mut link = root_path.to_str().unwrap().to_string();
+        if !id.is_empty() {
+            link = format!("{}#{}", link, id);
+        }
 
         fn get_text(node: Node) -> String {
             let mut text = "".to_string();
@@ -86,49 +314,126 @@ impl Page {
             text
         }
 
-        let text = get_text(node).replace("\n", "").trim().to_string();
+        let text =
+            collapse_whitespace(get_text(node).trim_matches(|c: char| c.is_ascii_whitespace()));
         (!text.is_empty()).then_some(ContentType::Text {
             text,
-            style: TextStyle::Underline,
+            style: style.underline(),
             hints: None,
             href: Some(link),
+            lang: Page::language(node),
+            dir: Page::direction(node),
         })
     }
 
-    fn parse_ruby(node: Node) -> Vec<ContentType> {
-        let mut rb = Vec::new();
-        let mut rt = Vec::new();
+    // The base text is in rb elements, or in the text before each rt, like "<ruby>漢<rt>かん</rt>
+    // </ruby>". The rb elements before the rt elements are paired with them in order.
+    fn parse_ruby(node: Node, style: TextStyle) -> Vec<ContentType> {
+        fn get_text(node: Node) -> String {
+            let text: String = node
+                .descendants()
+                .filter_map(|n| n.is_text().then(|| n.text()).flatten())
+                .collect();
+            collapse_whitespace(text.trim_matches(|c: char| c.is_ascii_whitespace()))
+        }
+
+        let mut result = vec![];
+        let mut push = |bases: &mut Vec<String>, hints: &mut Vec<String>| {
+            for (i, text) in bases.drain(..).enumerate() {
+                if text.is_empty() {
+                    continue;
+                }
+                result.push(ContentType::Text {
+                    text,
+                    style,
+                    hints: hints.get(i).filter(|x| !x.is_empty()).cloned(),
+                    href: None,
+                    lang: Page::language(node),
+                    dir: Page::direction(node),
+                });
+            }
+            hints.clear();
+        };
 
+        let mut bases: Vec<String> = vec![];
+        let mut hints = vec![];
+        // Whether the last base is text outside of an rb, which the next text is added to
+        let mut open = false;
         for ele in node.children() {
             match ele.tag_name().name() {
-                "rb" => rb.push(ele.text().unwrap().to_string()),
-                "rt" => rt.push(ele.text().unwrap().to_string()),
-                _ => (),
+                "rt" => hints.push(get_text(ele)),
+                "rp" => (),
+                name => {
+                    let text = get_text(ele);
+                    if text.is_empty() {
+                        continue;
+                    }
+                    // A base after the annotations starts the next pair
+                    if !hints.is_empty() {
+                        push(&mut bases, &mut hints);
+                        open = false;
+                    }
+                    match bases.last_mut() {
+                        Some(base) if open && name != "rb" => base.push_str(&text),
+                        _ => bases.push(text),
+                    }
+                    open = name != "rb";
+                }
             }
         }
+        push(&mut bases, &mut hints);
+
+        result
+    }
+
+    fn parse_list(node: Node, path: &Path, style: TextStyle) -> Vec<ContentType> {
+        let ordered = node.has_tag_name("ol");
+        let start = node
+            .attribute("start")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
 
         let mut result = vec![];
-        for i in 0..rb.len() {
-            result.push(ContentType::Text {
-                text: rb.get(i).unwrap().to_string(),
-                style: TextStyle::Regular,
-                hints: Some(rt.get(i).unwrap().to_string()),
-                href: None,
+        for (index, item) in node.children().filter(|n| n.has_tag_name("li")).enumerate() {
+            result.push(ContentType::ListItem {
+                number: ordered.then_some(start + index),
             });
+            result.extend(Page::parse_children(item, path, style));
+
+            if !matches!(Page::last(&result), Some(ContentType::LineBreak)) {
+                result.push(ContentType::LineBreak);
+            }
         }
 
         result
     }
 
-    fn parse_children(node: Node, path: &Path) -> Vec<ContentType> {
+    // The last element that was parsed, which is not an anchor
+    fn last(result: &[ContentType]) -> Option<&ContentType> {
+        result
+            .iter()
+            .rev()
+            .find(|x| !matches!(x, ContentType::Anchor(_)))
+    }
+
+    fn parse_children(node: Node, path: &Path, style: TextStyle) -> Vec<ContentType> {
         let mut result = Vec::new();
 
         if node.has_children() {
             for e in node.children() {
+                if let Some(id) = e.attribute("id") {
+                    result.push(ContentType::Anchor(id.to_string()));
+                }
+
                 match e.tag_name().name() {
-                    "div" | "svg" | "span" => result.extend(Page::parse_children(e, path)),
+                    "svg" if !Page::is_image_wrapper(e) => result.push(Page::parse_svg(e, path)),
+                    // The groups of an SVG that only wraps an image are flattened, see
+                    // is_image_wrapper
+                    "div" | "svg" | "span" | "g" => {
+                        result.extend(Page::parse_children(e, path, style))
+                    }
                     "p" => {
-                        let parsed = Page::parse_children(e, path);
+                        let parsed = Page::parse_children(e, path, style);
                         let show_push = parsed
                             .iter()
                             .find(|v| matches!(v, ContentType::Text { .. }))
@@ -141,25 +446,48 @@ impl Page {
                         }
                     }
                     "br" => result.push(ContentType::LineBreak),
-                    "ruby" => result.extend(Page::parse_ruby(e)),
-                    "a" => Page::parse_a(e, path)
+                    "ruby" => result.extend(Page::parse_ruby(e, style)),
+                    // An anchor without a link is only a target of the links
+                    "a" if e.attribute("href").is_none() => {
+                        result.extend(Page::parse_children(e, path, style))
+                    }
+                    "a" => Page::parse_a(e, path, style)
                         .map(|v| result.push(v))
                         .unwrap_or_default(),
-                    "image" | "img" => result.push(Page::parse_image_or_img(e, path)),
-                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "i" | "u" | "" => Page::parse_text(
-                        e,
-                        match e.tag_name().name() {
-                            "b" => TextStyle::Bold,
-                            "i" => TextStyle::Italic,
-                            "u" => TextStyle::Underline,
-                            "" => TextStyle::Regular,
-                            _ => TextStyle::Bold,
-                        },
-                    )
-                    .map(|v| result.push(v))
-                    .unwrap_or_default(),
-
-                    _ => println!("Unsupported Tag Name: <{}>", e.tag_name().name()),
+                    "image" | "img" => result.extend(Page::parse_image_or_img(e, path)),
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        let level = e.tag_name().name()[1..].parse().unwrap();
+                        let parsed = Page::parse_children(
+                            e,
+                            path,
+                            TextStyle {
+                                heading: Some(level),
+                                ..style
+                            },
+                        );
+                        if !parsed.iter().any(|v| matches!(v, ContentType::Text { .. })) {
+                            continue;
+                        }
+
+                        // A heading is a line on its own
+                        if matches!(Page::last(&result), Some(ContentType::Text { .. })) {
+                            result.push(ContentType::LineBreak);
+                        }
+                        result.extend(parsed);
+                        result.push(ContentType::LineBreak);
+                    }
+                    "b" | "strong" => result.extend(Page::parse_children(e, path, style.bold())),
+                    "i" | "em" => result.extend(Page::parse_children(e, path, style.italic())),
+                    "u" => result.extend(Page::parse_children(e, path, style.underline())),
+                    "ul" | "ol" => result.extend(Page::parse_list(e, path, style)),
+                    "" => Page::parse_text(e, style)
+                        .map(|v| result.push(v))
+                        .unwrap_or_default(),
+
+                    // The title and the description of an SVG are not shown
+                    "script" | "style" | "title" | "desc" => (),
+                    // Keep the text of the other elements
+                    _ => result.extend(Page::parse_children(e, path, style)),
                 }
             }
         }
@@ -167,7 +495,7 @@ impl Page {
         result
     }
 
-    pub fn new(content: String, title: Option<String>, path: &Path) -> Page {
+    pub fn new(content: String, title: Option<String>, path: &Path) -> Result<Page, String> {
         let doc = Document::parse_with_options(
             content.as_str(),
             ParsingOptions {
@@ -175,49 +503,245 @@ impl Page {
                 ..Default::default()
             },
         )
-        .unwrap();
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
         let root = doc.root_element();
-        let body = root.children().find(|e| e.has_tag_name("body")).unwrap();
-        let parsed = Page::parse_children(body, path);
+        let body = root
+            .children()
+            .find(|e| e.has_tag_name("body"))
+            .ok_or(format!("{}: the page has no body", path.display()))?;
+        let mut parsed = vec![];
+        let mut anchors = HashMap::new();
+        for content in Page::parse_children(body, path, TextStyle::default()) {
+            match content {
+                // The element is found by where its text starts, after the line breaks
+                ContentType::Anchor(id) => {
+                    anchors.entry(id).or_insert(parsed.len());
+                }
+                ContentType::LineBreak => {
+                    for index in anchors.values_mut() {
+                        if *index == parsed.len() {
+                            *index += 1;
+                        }
+                    }
+                    parsed.push(content);
+                }
+                content => parsed.push(content),
+            }
+        }
+
+        let svgs = body
+            .descendants()
+            .filter(|n| n.has_tag_name("svg") && !Page::is_image_wrapper(*n))
+            .filter(|n| !n.ancestors().skip(1).any(|a| a.has_tag_name("svg")))
+            .map(|n| (Page::svg_id(n, path), Page::svg_source(n, &content)))
+            .collect();
 
         let in_doc_title = root
             .descendants()
             .find(|x| x.tag_name().name() == "title")
-            .unwrap()
-            .text()
-            .unwrap()
+            .and_then(|x| x.text())
+            .unwrap_or_default()
             .to_string();
 
-        Page {
+        let length = parsed.iter().map(Page::text_length).sum();
+
+        Ok(Page {
             title: title.unwrap_or(in_doc_title),
             content: parsed,
+            svgs,
+            anchors,
+            length,
+        })
+    }
+
+    // Paths of all the images in this page, in reading order
+    pub fn images(&self) -> Vec<String> {
+        let mut images: Vec<String> = vec![];
+
+        for i in &self.content {
+            if let ContentType::Image { path, .. } | ContentType::Img { path, .. } = i {
+                if !images.contains(path) {
+                    images.push(path.clone());
+                }
+            }
+        }
+
+        images
+    }
+
+    // The number of characters of the element in the text of the page
+    fn text_length(content: &ContentType) -> usize {
+        match content {
+            ContentType::Text { text, .. } => text.chars().count(),
+            _ => 1,
         }
     }
 
-    #[allow(unused)]
-    pub fn print(&self) {
+    // The position in the text of the page of the character at the offset in the element
+    pub fn position(&self, content: usize, offset: usize) -> usize {
+        let start: usize = self
+            .content
+            .iter()
+            .take(content)
+            .map(Page::text_length)
+            .sum();
+
+        start + offset
+    }
+
+    // The element and the offset in it of the character at the position in the text of the page
+    pub fn anchor(&self, mut position: usize) -> (usize, usize) {
+        for (index, content) in self.content.iter().enumerate() {
+            let length = Page::text_length(content);
+            if position < length {
+                return (index, position);
+            }
+            position -= length;
+        }
+
+        (self.content.len(), 0)
+    }
+
+    // The text of the page, where each line break and image is a line break. Positions in the
+    // page, like the search results, are character offsets into this text.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+
         for i in &self.content {
             match i {
-                ContentType::Text {
-                    text,
-                    style: _,
-                    hints,
-                    href,
-                } => {
-                    if href.is_none() {
-                        if hints.is_none() {
-                            print!("{}", text)
-                        } else {
-                            print!("{}{{{}}}", text, hints.clone().unwrap())
-                        }
-                    } else {
-                        print!("[{}]({})", text, href.clone().unwrap())
-                    }
-                }
-                ContentType::Image(path) => println!("[Image]({})", path),
-                ContentType::Img(path) => println!("[Img]({})", path),
-                ContentType::LineBreak => println!(),
+                ContentType::Text { text: x, .. } => text.push_str(x),
+                _ => text.push('\n'),
             }
         }
+
+        text
+    }
+}
+
+// Whether the character is Chinese or Japanese, which have no spaces between words. Korean has
+// spaces between words, so Hangul is not included.
+fn is_cjk(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{2e80}'..='\u{2fdf}'
+            | '\u{3000}'..='\u{30ff}'
+            | '\u{3100}'..='\u{312f}'
+            | '\u{31f0}'..='\u{31ff}'
+            | '\u{3400}'..='\u{4dbf}'
+            | '\u{4e00}'..='\u{9fff}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{ff00}'..='\u{ffef}'
+            | '\u{20000}'..='\u{2ffff}'
+    )
+}
+
+// Whether a run of whitespace between the characters is shown as a space. The line breaks of the
+// source between CJK characters are removed, otherwise they would show up as spaces in the text.
+fn is_space(whitespace: &str, before: Option<char>, after: Option<char>) -> bool {
+    !(whitespace.contains('\n') && before.is_some_and(is_cjk) && after.is_some_and(is_cjk))
+}
+
+// Collapse the runs of whitespace into a space, like HTML does. Only the ASCII whitespace is
+// collapsed, as the ideographic and the no-break spaces are part of the text.
+fn collapse_whitespace(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, ch)) = chars.next() {
+        if !ch.is_ascii_whitespace() {
+            result.push(ch);
+            continue;
+        }
+
+        let mut end = start + 1;
+        while let Some((index, _)) = chars.next_if(|(_, x)| x.is_ascii_whitespace()) {
+            end = index + 1;
+        }
+        let after = chars.peek().map(|(_, x)| *x);
+        if is_space(&text[start..end], result.chars().last(), after) {
+            result.push(' ');
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(body: &str) -> Page {
+        let content = format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\"><body>{}</body></html>",
+            body
+        );
+        Page::new(content, None, Path::new("OEBPS/a.xhtml")).unwrap()
+    }
+
+    // The text of each element with its ruby text
+    fn ruby(body: &str) -> Vec<(String, Option<String>)> {
+        page(body)
+            .content
+            .into_iter()
+            .filter_map(|x| match x {
+                ContentType::Text { text, hints, .. } => Some((text, hints)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn pair(text: &str, hints: Option<&str>) -> (String, Option<String>) {
+        (text.to_string(), hints.map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn ruby_without_rb() {
+        assert_eq!(
+            ruby("<p><ruby>漢<rt>かん</rt>字<rt>じ</rt></ruby></p>"),
+            [pair("漢", Some("かん")), pair("字", Some("じ"))]
+        );
+        assert_eq!(
+            ruby("<p><ruby>明日<rp>(</rp><rt>あした</rt><rp>)</rp></ruby></p>"),
+            [pair("明日", Some("あした"))]
+        );
+    }
+
+    #[test]
+    fn ruby_with_rb() {
+        assert_eq!(
+            ruby("<p><ruby><rb>東</rb><rb>京</rb><rt>とう</rt><rt>きょう</rt></ruby></p>"),
+            [pair("東", Some("とう")), pair("京", Some("きょう"))]
+        );
+        // The text in the elements of the base, and the bases without their rt
+        assert_eq!(
+            ruby("<p><ruby><rb><span>東</span></rb><rb>京</rb><rt>とう</rt></ruby></p>"),
+            [pair("東", Some("とう")), pair("京", None)]
+        );
+    }
+
+    #[test]
+    fn images_without_source_are_skipped() {
+        let page = page("<p>a</p><img alt=\"none\"/><p>b</p>");
+        assert!(!page
+            .content
+            .iter()
+            .any(|x| matches!(x, ContentType::Img { .. })));
+    }
+
+    #[test]
+    fn anchors_point_to_the_text() {
+        let page =
+            page("<p>a</p><h2 id=\"target\">Title</h2><p>b <span id=\"inline\">c</span></p>");
+        let text = |id: &str| match &page.content[page.anchors[id]] {
+            ContentType::Text { text, .. } => text.clone(),
+            _ => String::new(),
+        };
+
+        assert_eq!(text("target"), "Title");
+        assert_eq!(text("inline"), "c");
+        assert!(!page
+            .content
+            .iter()
+            .any(|x| matches!(x, ContentType::Anchor(_))));
     }
 }
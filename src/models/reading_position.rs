@@ -1,41 +1,63 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::{self, File},
-    io::{self, BufReader, BufWriter, Read},
-    path::PathBuf,
+    fs::File,
+    io::{self, BufReader, BufWriter},
 };
 
-#[derive(Serialize, Deserialize)]
+use super::cache::cache_file;
+
+pub enum Anchor {
+    // The element in Page::content and the offset in characters in its text, which do not
+    // depend on the size of the screen
+    Content(usize, usize),
+    // A rendered line, which was saved by the older versions
+    Line(usize),
+}
+
 pub struct ReadingPosition {
     pub page: String,
-    pub offset: usize,
+    pub anchor: Anchor,
+}
+
+// The format of the position file, the older versions saved only the page and the line as offset
+#[derive(Serialize, Deserialize)]
+struct SavedPosition {
+    page: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<usize>,
+    offset: usize,
 }
 
 impl ReadingPosition {
-    pub fn new(page: String, offset: usize) -> Self {
-        Self { page, offset }
+    pub fn new(page: String, content: usize, offset: usize) -> Self {
+        Self {
+            page,
+            anchor: Anchor::Content(content, offset),
+        }
     }
 
     pub fn save(&self, book_hash: &str) -> io::Result<()> {
-        let mut cache_dir = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
-        cache_dir.push("ers");
-        fs::create_dir_all(&cache_dir)?;
+        let position_file = cache_file(&format!("{}.json", book_hash))?;
 
-        let mut position_file = cache_dir;
-        position_file.push(format!("{}.json", book_hash));
+        let (content, offset) = match self.anchor {
+            Anchor::Content(content, offset) => (Some(content), offset),
+            Anchor::Line(line) => (None, line),
+        };
+        let saved = SavedPosition {
+            page: self.page.clone(),
+            content,
+            offset,
+        };
 
         let file = File::create(position_file)?;
         let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, self)?;
+        serde_json::to_writer(writer, &saved)?;
 
         Ok(())
     }
 
     pub fn load(book_hash: &str) -> io::Result<Option<Self>> {
-        let mut cache_dir = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
-        cache_dir.push("ers");
-        let mut position_file = cache_dir;
-        position_file.push(format!("{}.json", book_hash));
+        let position_file = cache_file(&format!("{}.json", book_hash))?;
 
         if !position_file.exists() {
             return Ok(None);
@@ -43,15 +65,16 @@ impl ReadingPosition {
 
         let file = File::open(position_file)?;
         let reader = BufReader::new(file);
-        let position: ReadingPosition = serde_json::from_reader(reader)?;
+        let saved: SavedPosition = serde_json::from_reader(reader)?;
 
-        Ok(Some(position))
-    }
-}
+        let anchor = match saved.content {
+            Some(content) => Anchor::Content(content, saved.offset),
+            None => Anchor::Line(saved.offset),
+        };
 
-pub fn calculate_book_hash(book_path: &str) -> io::Result<String> {
-    let mut file = File::open(book_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    Ok(format!("{:x}", md5::compute(&buffer)))
+        Ok(Some(ReadingPosition {
+            page: saved.page,
+            anchor,
+        }))
+    }
 }
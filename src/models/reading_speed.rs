@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    time::{Duration, Instant},
+};
+
+use super::cache::cache_file;
+
+const READING_SPEED_FILE: &str = "reading_speed.json";
+
+// Roughly 200 words per minute, used until the reading speed is measured
+const DEFAULT_CHARS_PER_MINUTE: f64 = 1000.0;
+
+// Pauses longer than this are not counted as reading
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Moving faster than this is skimming or skipping, which is not counted as reading
+const MAX_CHARS_PER_MINUTE: f64 = 5000.0;
+
+// The reading time that is collected before the average is updated
+const SAMPLE_DURATION: Duration = Duration::from_secs(60);
+
+// How much a new sample changes the average
+const SMOOTHING: f64 = 0.2;
+
+// A running average of the reading speed, shared by all the books
+#[derive(Serialize, Deserialize)]
+pub struct ReadingSpeed {
+    pub chars_per_minute: f64,
+    // The last position in characters and when it was reached
+    #[serde(skip)]
+    last: Option<(Instant, usize)>,
+    // The reading time and the characters read since the last sample
+    #[serde(skip)]
+    sample: (Duration, usize),
+}
+
+impl Default for ReadingSpeed {
+    fn default() -> Self {
+        Self {
+            chars_per_minute: DEFAULT_CHARS_PER_MINUTE,
+            last: None,
+            sample: (Duration::ZERO, 0),
+        }
+    }
+}
+
+impl ReadingSpeed {
+    pub fn load() -> Self {
+        cache_file(READING_SPEED_FILE)
+            .and_then(File::open)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let file = File::create(cache_file(READING_SPEED_FILE)?)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self)?;
+
+        Ok(())
+    }
+
+    // Record the position in characters through the book
+    pub fn update(&mut self, position: usize) {
+        let now = Instant::now();
+
+        if let Some((time, last_position)) = self.last {
+            if position == last_position {
+                return;
+            }
+
+            let elapsed = now - time;
+            if position > last_position && elapsed < IDLE_TIMEOUT {
+                let read = position - last_position;
+
+                if read as f64 / elapsed.as_secs_f64() * 60.0 <= MAX_CHARS_PER_MINUTE {
+                    self.sample.0 += elapsed;
+                    self.sample.1 += read;
+                }
+            }
+        }
+        self.last = Some((now, position));
+
+        if self.sample.0 >= SAMPLE_DURATION {
+            let speed = self.sample.1 as f64 / self.sample.0.as_secs_f64() * 60.0;
+            self.chars_per_minute += (speed - self.chars_per_minute) * SMOOTHING;
+            self.sample = (Duration::ZERO, 0);
+        }
+    }
+
+    // The time needed to read the characters
+    pub fn time_left(&self, chars: usize) -> Duration {
+        Duration::from_secs_f64(chars as f64 / self.chars_per_minute.max(1.0) * 60.0)
+    }
+}
@@ -0,0 +1,68 @@
+use regex::RegexBuilder;
+
+use super::book::Book;
+
+// Number of characters shown around a match in the results
+const CONTEXT_LENGTH: usize = 30;
+
+pub struct SearchHit {
+    pub page: String,
+    // The range of the match in characters in the text of the page, see Page::text
+    pub start: usize,
+    pub end: usize,
+    // The text before the match, the match and the text after it
+    pub context: (String, String, String),
+}
+
+// Search all the pages in reading order, ignoring case. The query is matched literally unless
+// regex is set.
+pub fn search(book: &Book, query: &str, regex: bool) -> Result<Vec<SearchHit>, regex::Error> {
+    let pattern = if regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+    let re = RegexBuilder::new(&pattern).case_insensitive(true).build()?;
+
+    let mut hits = vec![];
+    for page in &book.order {
+        let text = book.pages.get(page).unwrap().text();
+
+        // The matches are in bytes, so count the characters up to each match
+        let mut chars = 0;
+        let mut last = 0;
+        for m in re.find_iter(&text) {
+            if m.is_empty() {
+                continue;
+            }
+
+            let start = chars + text[last..m.start()].chars().count();
+            let end = start + m.as_str().chars().count();
+            chars = end;
+            last = m.end();
+
+            let before: String = text[..m.start()]
+                .chars()
+                .rev()
+                .take(CONTEXT_LENGTH)
+                .collect::<Vec<char>>()
+                .into_iter()
+                .rev()
+                .collect();
+            let after: String = text[m.end()..].chars().take(CONTEXT_LENGTH).collect();
+
+            hits.push(SearchHit {
+                page: page.clone(),
+                start,
+                end,
+                context: (
+                    before.replace('\n', " "),
+                    m.as_str().replace('\n', " "),
+                    after.replace('\n', " "),
+                ),
+            });
+        }
+    }
+
+    Ok(hits)
+}
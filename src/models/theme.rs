@@ -0,0 +1,336 @@
+use ratatui::style::{Color, Modifier, Style};
+use std::{collections::BTreeMap, env, str::FromStr};
+
+use super::{
+    highlight::HighlightColor,
+    page::{ContentType, TextStyle},
+};
+
+pub const BUILT_IN_THEMES: [&str; 4] = ["dark", "light", "sepia", "high-contrast"];
+
+// The colors of the screens. Reset is the color of the terminal.
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub background: Color,
+    pub text: Color,
+    // The italic and underlined text is shown in this color if the terminal cannot show it
+    pub emphasis: Color,
+    pub link: Color,
+    pub heading: Color,
+    pub border: Color,
+    // The labels, the borders of the lists and the prompts
+    pub accent: Color,
+    // The background of the selected entry of the lists
+    pub selection: Color,
+    // The text on the selection, the search matches and the highlights
+    pub highlight_text: Color,
+    pub status: Color,
+    pub instructions: Color,
+    // The book title, the notes and the bookmark names
+    pub title: Color,
+    pub entry: Color,
+    // The details of the entries and the placeholders
+    pub dim: Color,
+    pub error: Color,
+    pub search_match: Color,
+    pub current_match: Color,
+    // The backgrounds of the highlights of each color
+    pub highlight_yellow: Color,
+    pub highlight_green: Color,
+    pub highlight_blue: Color,
+    pub highlight_pink: Color,
+    // Whether the terminal can show italic and underlined text
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Theme {
+    pub fn built_in(name: &str) -> Option<Theme> {
+        let theme = Theme {
+            background: Color::Reset,
+            text: Color::Reset,
+            emphasis: Color::LightYellow,
+            link: Color::LightBlue,
+            heading: Color::White,
+            border: Color::Reset,
+            accent: Color::LightCyan,
+            selection: Color::LightCyan,
+            highlight_text: Color::Black,
+            status: Color::LightCyan,
+            instructions: Color::LightYellow,
+            title: Color::LightMagenta,
+            entry: Color::LightBlue,
+            dim: Color::DarkGray,
+            error: Color::LightRed,
+            search_match: Color::Yellow,
+            current_match: Color::LightRed,
+            highlight_yellow: Color::LightYellow,
+            highlight_green: Color::LightGreen,
+            highlight_blue: Color::LightBlue,
+            highlight_pink: Color::LightMagenta,
+            italic: true,
+            underline: true,
+        };
+
+        match name {
+            "dark" => Some(theme),
+            "light" => Some(Theme {
+                background: Color::Rgb(250, 250, 245),
+                text: Color::Rgb(30, 30, 30),
+                emphasis: Color::Rgb(170, 70, 0),
+                link: Color::Rgb(0, 80, 180),
+                heading: Color::Rgb(110, 30, 110),
+                border: Color::Rgb(150, 150, 150),
+                accent: Color::Rgb(0, 110, 140),
+                selection: Color::Rgb(0, 110, 140),
+                highlight_text: Color::Rgb(250, 250, 245),
+                status: Color::Rgb(0, 110, 140),
+                instructions: Color::Rgb(140, 90, 0),
+                title: Color::Rgb(150, 40, 120),
+                entry: Color::Rgb(0, 80, 180),
+                dim: Color::Rgb(130, 130, 130),
+                error: Color::Rgb(200, 30, 30),
+                search_match: Color::Rgb(255, 225, 110),
+                current_match: Color::Rgb(255, 150, 120),
+                highlight_yellow: Color::Rgb(190, 140, 0),
+                highlight_green: Color::Rgb(40, 130, 60),
+                highlight_blue: Color::Rgb(40, 100, 190),
+                highlight_pink: Color::Rgb(180, 60, 130),
+                ..theme
+            }),
+            "sepia" => Some(Theme {
+                background: Color::Rgb(244, 236, 216),
+                text: Color::Rgb(91, 70, 54),
+                emphasis: Color::Rgb(160, 82, 45),
+                link: Color::Rgb(30, 90, 130),
+                heading: Color::Rgb(120, 60, 20),
+                border: Color::Rgb(170, 150, 120),
+                accent: Color::Rgb(140, 100, 60),
+                selection: Color::Rgb(140, 100, 60),
+                highlight_text: Color::Rgb(244, 236, 216),
+                status: Color::Rgb(120, 90, 60),
+                instructions: Color::Rgb(150, 100, 40),
+                title: Color::Rgb(130, 50, 40),
+                entry: Color::Rgb(30, 90, 130),
+                dim: Color::Rgb(160, 140, 115),
+                error: Color::Rgb(180, 40, 30),
+                search_match: Color::Rgb(240, 200, 110),
+                current_match: Color::Rgb(230, 140, 100),
+                highlight_yellow: Color::Rgb(175, 125, 20),
+                highlight_green: Color::Rgb(80, 120, 50),
+                highlight_blue: Color::Rgb(50, 100, 150),
+                highlight_pink: Color::Rgb(160, 70, 110),
+                ..theme
+            }),
+            "high-contrast" => Some(Theme {
+                background: Color::Black,
+                text: Color::White,
+                emphasis: Color::LightYellow,
+                link: Color::LightCyan,
+                heading: Color::LightYellow,
+                border: Color::White,
+                accent: Color::LightCyan,
+                selection: Color::LightYellow,
+                highlight_text: Color::Black,
+                status: Color::White,
+                instructions: Color::LightYellow,
+                title: Color::LightYellow,
+                entry: Color::LightCyan,
+                dim: Color::Gray,
+                error: Color::LightRed,
+                search_match: Color::LightYellow,
+                current_match: Color::LightGreen,
+                highlight_blue: Color::LightCyan,
+                ..theme
+            }),
+            _ => None,
+        }
+    }
+
+    // A user-defined theme, which changes the colors of its base theme
+    pub fn user_defined(colors: &BTreeMap<String, String>) -> Result<Theme, String> {
+        let base = colors.get("base").map(|x| x.as_str()).unwrap_or("dark");
+        let mut theme = Theme::built_in(base).ok_or(format!("unknown base theme '{}'", base))?;
+
+        for (name, value) in colors.iter().filter(|(x, _)| *x != "base") {
+            let color = Color::from_str(value).map_err(|_| format!("invalid color '{}'", value))?;
+
+            *match name.as_str() {
+                "background" => &mut theme.background,
+                "text" => &mut theme.text,
+                "emphasis" => &mut theme.emphasis,
+                "link" => &mut theme.link,
+                "heading" => &mut theme.heading,
+                "border" => &mut theme.border,
+                "accent" => &mut theme.accent,
+                "selection" => &mut theme.selection,
+                "highlight_text" => &mut theme.highlight_text,
+                "status" => &mut theme.status,
+                "instructions" => &mut theme.instructions,
+                "title" => &mut theme.title,
+                "entry" => &mut theme.entry,
+                "dim" => &mut theme.dim,
+                "error" => &mut theme.error,
+                "search_match" => &mut theme.search_match,
+                "current_match" => &mut theme.current_match,
+                "highlight_yellow" => &mut theme.highlight_yellow,
+                "highlight_green" => &mut theme.highlight_green,
+                "highlight_blue" => &mut theme.highlight_blue,
+                "highlight_pink" => &mut theme.highlight_pink,
+                _ => return Err(format!("unknown theme color '{}'", name)),
+            } = color;
+        }
+
+        Ok(theme)
+    }
+
+    // The theme as the terminal can show it, the colors are reduced to the 16 colors of the
+    // terminal if it cannot show true colors
+    pub fn for_terminal(self, true_color: bool, italic: bool, underline: bool) -> Theme {
+        let color = |x: Color| if true_color { x } else { nearest_ansi(x) };
+
+        Theme {
+            background: color(self.background),
+            text: color(self.text),
+            emphasis: color(self.emphasis),
+            link: color(self.link),
+            heading: color(self.heading),
+            border: color(self.border),
+            accent: color(self.accent),
+            selection: color(self.selection),
+            highlight_text: color(self.highlight_text),
+            status: color(self.status),
+            instructions: color(self.instructions),
+            title: color(self.title),
+            entry: color(self.entry),
+            dim: color(self.dim),
+            error: color(self.error),
+            search_match: color(self.search_match),
+            current_match: color(self.current_match),
+            highlight_yellow: color(self.highlight_yellow),
+            highlight_green: color(self.highlight_green),
+            highlight_blue: color(self.highlight_blue),
+            highlight_pink: color(self.highlight_pink),
+            italic,
+            underline,
+        }
+    }
+
+    // The style of the highlights of the color
+    pub fn highlight(&self, color: HighlightColor) -> Style {
+        let background = match color {
+            HighlightColor::Yellow => self.highlight_yellow,
+            HighlightColor::Green => self.highlight_green,
+            HighlightColor::Blue => self.highlight_blue,
+            HighlightColor::Pink => self.highlight_pink,
+        };
+
+        Style::new().fg(self.highlight_text).bg(background)
+    }
+
+    // The style of the text of an element of a page
+    pub fn content_style(&self, content: &ContentType) -> Style {
+        let text = Style::new().fg(self.text);
+
+        match content {
+            ContentType::Text { style, .. } if style.heading.is_some() => {
+                Style::new().fg(self.heading).add_modifier(Modifier::BOLD)
+            }
+            ContentType::Text { style, href, .. } => {
+                let base = match (href, style) {
+                    (Some(_), _) => self.underlined(self.link),
+                    (
+                        _,
+                        TextStyle {
+                            underline: true, ..
+                        },
+                    ) => self.underlined(self.text),
+                    (_, TextStyle { italic: true, .. }) => self.italic(self.text),
+                    _ => text,
+                };
+                // The styles are combined, the emphasis color only stands in for the italic
+                // of plain text
+                let base = if style.italic && self.italic {
+                    base.add_modifier(Modifier::ITALIC)
+                } else {
+                    base
+                };
+
+                if style.bold {
+                    base.add_modifier(Modifier::BOLD)
+                } else {
+                    base
+                }
+            }
+            // The placeholders of the images
+            ContentType::Image { .. } | ContentType::Img { .. } => self.italic(self.dim),
+            ContentType::LineBreak | ContentType::ListItem { .. } | ContentType::Anchor(_) => text,
+        }
+    }
+
+    fn italic(&self, color: Color) -> Style {
+        if self.italic {
+            Style::new().fg(color).add_modifier(Modifier::ITALIC)
+        } else {
+            Style::new().fg(self.emphasis)
+        }
+    }
+
+    fn underlined(&self, color: Color) -> Style {
+        if self.underline {
+            Style::new().fg(color).add_modifier(Modifier::UNDERLINED)
+        } else if color == self.text {
+            Style::new().fg(self.emphasis)
+        } else {
+            Style::new().fg(color)
+        }
+    }
+}
+
+// Whether the terminal says that it can show true colors
+pub fn supports_true_color() -> bool {
+    env::var("COLORTERM").is_ok_and(|x| x == "truecolor" || x == "24bit")
+}
+
+// Whether the terminal can show italic text, the Linux console and the old terminals cannot
+pub fn supports_italic() -> bool {
+    env::var("TERM").is_ok_and(|x| x != "linux" && x != "screen" && !x.starts_with("vt"))
+}
+
+// Whether the terminal can show underlined text
+pub fn supports_underline() -> bool {
+    env::var("TERM").is_ok_and(|x| x != "linux")
+}
+
+// The closest of the 16 colors of the terminal, with the colors of xterm
+fn nearest_ansi(color: Color) -> Color {
+    const PALETTE: [(Color, (i32, i32, i32)); 16] = [
+        (Color::Black, (0, 0, 0)),
+        (Color::Red, (205, 0, 0)),
+        (Color::Green, (0, 205, 0)),
+        (Color::Yellow, (205, 205, 0)),
+        (Color::Blue, (0, 0, 238)),
+        (Color::Magenta, (205, 0, 205)),
+        (Color::Cyan, (0, 205, 205)),
+        (Color::Gray, (229, 229, 229)),
+        (Color::DarkGray, (127, 127, 127)),
+        (Color::LightRed, (255, 0, 0)),
+        (Color::LightGreen, (0, 255, 0)),
+        (Color::LightYellow, (255, 255, 0)),
+        (Color::LightBlue, (92, 92, 255)),
+        (Color::LightMagenta, (255, 0, 255)),
+        (Color::LightCyan, (0, 255, 255)),
+        (Color::White, (255, 255, 255)),
+    ];
+
+    let Color::Rgb(r, g, b) = color else {
+        return color;
+    };
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+
+    PALETTE
+        .iter()
+        .min_by_key(|(_, (x, y, z))| (r - x).pow(2) + (g - y).pow(2) + (b - z).pow(2))
+        .map(|(x, _)| *x)
+        .unwrap()
+}
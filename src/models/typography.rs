@@ -0,0 +1,81 @@
+use ratatui::layout::Rect;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+};
+
+use super::{cache::cache_file, config::Config};
+
+// The narrowest that the column of text can be made, in cells
+pub const MIN_WIDTH: u16 = 20;
+
+// How the text of a book is laid out, which is saved for each book
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Typography {
+    // The maximum number of cells of a line, the column of text is centered. None if the text
+    // fills the screen.
+    pub max_width: Option<u16>,
+    // The space on the left and the right of the text, in cells
+    pub margin: u16,
+    // The number of blank lines between paragraphs
+    pub paragraph_spacing: usize,
+    // The indentation of the first line of the paragraphs, in cells
+    pub indent: usize,
+    pub justify: bool,
+    // Whether words are hyphenated with the patterns of their language
+    #[serde(default)]
+    pub hyphenate: bool,
+}
+
+impl Typography {
+    // The typography of the books that were not changed
+    pub fn new(config: &Config) -> Self {
+        Typography {
+            max_width: config.text.max_width,
+            margin: config.margins.horizontal,
+            paragraph_spacing: config.text.paragraph_spacing,
+            indent: config.text.indent,
+            justify: config.text.justify,
+            hyphenate: config.text.hyphenate,
+        }
+    }
+
+    // The column of text in the area
+    pub fn column(&self, area: Rect) -> Rect {
+        let width = self
+            .max_width
+            .map(|x| x.min(area.width))
+            .unwrap_or(area.width);
+
+        Rect {
+            x: area.x + (area.width - width) / 2,
+            width,
+            ..area
+        }
+    }
+
+    pub fn save(&self, book_hash: &str) -> io::Result<()> {
+        let typography_file = cache_file(&format!("{}.typography.json", book_hash))?;
+
+        let file = File::create(typography_file)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self)?;
+
+        Ok(())
+    }
+
+    pub fn load(book_hash: &str) -> io::Result<Option<Self>> {
+        let typography_file = cache_file(&format!("{}.typography.json", book_hash))?;
+
+        if !typography_file.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(typography_file)?;
+        let reader = BufReader::new(file);
+        let typography = serde_json::from_reader(reader)?;
+
+        Ok(Some(typography))
+    }
+}
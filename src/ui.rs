@@ -1,37 +1,211 @@
+use std::{ops::Range, sync::mpsc::Sender, time::Duration};
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
 use ratatui::{
-    layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Style, Stylize},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
+    style::{Style, Stylize},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Padding, Paragraph, Wrap},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, Padding, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Wrap,
+    },
     Frame,
 };
-use ratatui_image::{CropOptions, Resize};
+use ratatui_image::{picker::Picker, protocol::StatefulProtocol, CropOptions, Resize};
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    app::{App, Screen},
-    models::page::{ContentType, TextStyle},
+    app::{App, ClickTarget, ReadingMode, Screen, Zoom},
+    layout::{image_placeholder, Fragment, Item},
+    models::{
+        image_cache::{image_id, image_id_parts, MemoryCache},
+        keymap::Action,
+        page::ContentType,
+        theme::Theme,
+    },
     widgets::custom_thread_image::{ThreadImage, ThreadProtocol},
 };
 
 pub fn ui(frame: &mut Frame, app: &mut App) {
+    // The background and the text color of the theme, which the screens are drawn over
+    frame.render_widget(
+        Block::new().style(Style::new().fg(app.theme.text).bg(app.theme.background)),
+        frame.area(),
+    );
+
     match app.current_screen {
+        Screen::Bookmarks { .. } => render_bookmarks(frame, app),
         Screen::Info { .. } => render_info(frame, app),
+        Screen::Library { .. } => render_library(frame, app),
         Screen::Reading { .. } => render_reading(frame, app),
+        Screen::Viewer { .. } => render_viewer(frame, app),
+    }
+
+    if app.search_prompt.is_some() {
+        render_search(frame, app);
+    }
+    if app.bookmark_prompt.is_some() {
+        render_bookmark_prompt(frame, app);
+    }
+    if app.highlight_prompt.is_some() {
+        render_highlight_prompt(frame, app);
+    }
+    if app.message.is_some() {
+        render_message(frame, app);
     }
 }
 
-fn render_info(frame: &mut Frame, app: &mut App) {
-    let instruction = match &app.current_screen {
-        Screen::Info { prev_screen, .. } if prev_screen.is_none() => {
-            "[Up/Down ► Navigate] [Enter ► Start Reading] [Q ► Quit]"
-        }
-        Screen::Info { .. } => {
-            "[Esc ► Return] [Up/Down ► Navigate] [Enter ► Start Reading] [Q ► Quit]"
+// The message is shown over the last row of the screen
+fn render_message(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let area = frame.area();
+    let area = Rect {
+        y: area.bottom().saturating_sub(1),
+        height: area.height.min(1),
+        ..area
+    };
+
+    let line = match app.message.as_ref().unwrap() {
+        Ok(message) => Line::from(format!(" {}", message)).fg(theme.status),
+        // The TOML errors quote the config over several lines, the location is in the first line
+        // and the cause is in the last line
+        Err(err) => {
+            let first = err.lines().next().unwrap_or_default();
+            let last = err.lines().last().unwrap_or_default();
+            let err = if first == last {
+                first.to_string()
+            } else {
+                format!("{}: {}", first, last)
+            };
+
+            Line::from(format!(" {}", err)).fg(theme.error)
         }
-        _ => unreachable!(),
+    };
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(line, area);
+}
+
+fn render_bookmarks(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let keys = &app.config.keys;
+    let instructions = Paragraph::new(format!(
+        "[Esc ► Return] [{}/{} ► Navigate] [Enter ► Go To Bookmark] [{} ► Delete] [{} ► Quit]",
+        keys.label(Action::ScrollUp),
+        keys.label(Action::ScrollDown),
+        keys.label(Action::Delete),
+        keys.label(Action::Quit)
+    ))
+    .style(Style::default().fg(theme.instructions))
+    .alignment(Alignment::Center)
+    .wrap(Wrap { trim: true })
+    .block(Block::new().padding(Padding::horizontal(2)));
+    let instructions_line = instructions.line_count(frame.area().width) as u16;
+    let instruction_chunk = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage(100),
+            Constraint::Length(instructions_line),
+        ])
+        .split(frame.area());
+    frame.render_widget(instructions, instruction_chunk[1]);
+
+    let main_block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
+        .title(" 🔖 Bookmarks ")
+        .padding(Padding::symmetric(2, 1));
+    let main_area = instruction_chunk[0];
+    let inner_area = main_block.inner(main_area);
+    frame.render_widget(main_block, main_area);
+
+    if app.bookmarks.bookmarks.is_empty() {
+        let placeholder = Paragraph::new(format!(
+            "No bookmarks yet, press {} while reading to add one",
+            app.config.keys.label(Action::AddBookmark)
+        ))
+        .style(Style::new().fg(theme.dim))
+        .alignment(Alignment::Center);
+        frame.render_widget(placeholder, inner_area);
+        return;
+    }
+
+    let items: Vec<ListItem> = app
+        .bookmarks
+        .bookmarks
+        .iter()
+        .map(|bookmark| {
+            let title = app
+                .book
+                .pages
+                .get(&bookmark.page)
+                .map(|x| x.title.as_str())
+                .unwrap_or_default();
+
+            ListItem::new(vec![
+                Line::from(vec![
+                    Span::styled(bookmark.name.as_str(), Style::new().bold().fg(theme.entry)),
+                    Span::styled(format!(" - {}", title), Style::new().fg(theme.accent)),
+                ]),
+                Line::from(Span::styled(
+                    bookmark.excerpt.as_str(),
+                    Style::new().fg(theme.dim),
+                )),
+            ])
+        })
+        .collect();
+
+    let list = List::new(items)
+        .highlight_style(
+            Style::default()
+                .bg(theme.selection)
+                .fg(theme.highlight_text),
+        )
+        .highlight_symbol(" ► ");
+
+    if let Screen::Bookmarks { list_state, .. } = &mut app.current_screen {
+        frame.render_stateful_widget(list, inner_area, list_state);
+    }
+}
+
+fn render_info(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let Screen::Info {
+        filter,
+        entries,
+        filtering,
+        prev_screen,
+        ..
+    } = &app.current_screen
+    else {
+        unreachable!()
+    };
+    // The list state is borrowed when the table of contents is rendered
+    let (filter, filtering) = (filter.clone(), *filtering);
+    let keys = &app.config.keys;
+    let instruction = if filtering {
+        "[Enter ► Apply Filter] [Esc ► Clear Filter]".to_string()
+    } else {
+        format!(
+            "{}[{}/{} ► Navigate] [{}/{}/Home/End ► Jump] [Enter ► Start Reading] [{} ► Filter] [{} ► Library] [{} ► Quit]",
+            if !filter.is_empty() {
+                "[Esc ► Clear Filter] "
+            } else if prev_screen.is_some() {
+                "[Esc ► Return] "
+            } else {
+                ""
+            },
+            keys.label(Action::ScrollUp),
+            keys.label(Action::ScrollDown),
+            keys.label(Action::PageUp),
+            keys.label(Action::PageDown),
+            keys.label(Action::Search),
+            keys.label(Action::Library),
+            keys.label(Action::Quit)
+        )
     };
     let instructions = Paragraph::new(instruction)
-        .style(Style::default().light_yellow())
+        .style(Style::default().fg(theme.instructions))
         .alignment(Alignment::Center)
         .wrap(Wrap { trim: true })
         .block(Block::new().padding(Padding::horizontal(2)));
@@ -47,6 +221,7 @@ fn render_info(frame: &mut Frame, app: &mut App) {
 
     let main_block = Block::default()
         .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
         .title(" 📔 Book Info ")
         .padding(Padding::symmetric(2, 1));
     let main_area = instruction_chunk[0];
@@ -68,29 +243,62 @@ fn render_info(frame: &mut Frame, app: &mut App) {
         .split(chunks[2]);
 
     let title_block = Paragraph::new(app.book.title.as_str())
-        .style(Style::new().bold().light_magenta())
+        .style(Style::new().bold().fg(theme.title))
         .wrap(Wrap { trim: false });
     frame.render_widget(title_block, right_chunks[0]);
 
-    // Render TOC
-    let mut contents = vec![];
-    for ele in &app.book.toc {
-        contents.push(ListItem::from(Span::styled(
-            ele.1.to_string(),
-            Style::new().underlined().bold().light_blue(),
-        )));
+    // Render TOC, with the runs of matched characters of the filter highlighted
+    let style = Style::new().underlined().bold().fg(theme.entry);
+    let matched_style = style.fg(theme.highlight_text).bg(theme.search_match);
+    let contents: Vec<ListItem> = entries
+        .iter()
+        .map(|(index, matched)| {
+            let chars: Vec<(bool, char)> = app.book.toc[*index]
+                .2
+                .chars()
+                .enumerate()
+                .map(|(x, ch)| (matched.contains(&x), ch))
+                .collect();
+            let spans: Vec<Span> = chars
+                .chunk_by(|a, b| a.0 == b.0)
+                .map(|run| {
+                    let text: String = run.iter().map(|x| x.1).collect();
+                    Span::styled(text, if run[0].0 { matched_style } else { style })
+                })
+                .collect();
+
+            ListItem::from(Line::from(spans))
+        })
+        .collect();
+    let count = entries.len();
+
+    let mut title = vec![Span::raw("Table of Contents")];
+    if filtering || !filter.is_empty() {
+        title.push(Span::raw(" · Filter: "));
+        title.push(Span::styled(filter.as_str(), Style::new().bold()));
+        if filtering {
+            title.push(Span::raw("▏"));
+        }
+    }
+    if !filter.is_empty() {
+        title.push(Span::raw(format!(" · {} of {}", count, app.book.toc.len())));
     }
+    let toc_block = Block::default()
+        .borders(Borders::TOP)
+        .border_style(Style::new().fg(theme.accent))
+        .padding(Padding::horizontal(1))
+        .title(Line::from(title))
+        .title_style(Style::new().fg(theme.accent));
+    // The entries are clicked by their row
+    app.toc_area = toc_block.inner(right_chunks[1]);
 
     let toc = List::new(contents)
-        .block(
-            Block::default()
-                .borders(Borders::TOP)
-                .border_style(Style::new().light_cyan())
-                .padding(Padding::horizontal(1))
-                .title("Table of Contents")
-                .title_style(Style::new().light_cyan()),
+        .block(toc_block)
+        .highlight_style(
+            Style::default()
+                .bg(theme.selection)
+                .fg(theme.highlight_text),
         )
-        .highlight_style(Style::default().bg(Color::LightCyan).fg(Color::Black))
         .highlight_symbol(" ► ");
 
     if let Screen::Info { toc_state, .. } = &mut app.current_screen {
@@ -100,60 +308,91 @@ fn render_info(frame: &mut Frame, app: &mut App) {
     }
 
     // Render Cover
-    if app.book.cover.is_some() {
-        let cover_path = app.book.cover.clone().unwrap();
-        let cover_state = app.image_state.get_mut(&cover_path);
-        if cover_state.is_none() {
-            let dyn_img = app.book.images.get_mut(&cover_path).unwrap().get();
-
-            app.image_state.insert(
-                cover_path,
-                ThreadProtocol::new(
-                    app.tx_worker.clone(),
-                    app.picker.new_resize_protocol(dyn_img.clone()),
-                ),
-            );
-        } else {
-            let image = ThreadImage::new(cover_path);
-            frame.render_stateful_widget(image, chunks[0], cover_state.unwrap());
+    let placeholder = match (&app.book.cover, &mut app.picker) {
+        (Some(cover_path), Some(picker)) => {
+            let id = image_id(cover_path, "cover");
+
+            if let Some(cover_state) = app.image_state.get_mut(&id) {
+                let image = ThreadImage::new(id);
+                frame.render_stateful_widget(image, chunks[0], cover_state);
+            } else {
+                let max_size = max_image_size(frame.area(), picker);
+                let dyn_img = app
+                    .decoded_images
+                    .get(&app.book.images, cover_path, max_size)
+                    .clone();
+
+                insert_image_state(&mut app.image_state, &app.tx_worker, picker, id, dyn_img);
+            }
+
+            None
         }
-    } else {
-        let block_widget = Block::default().borders(Borders::ALL);
-        let block_content_area = block_widget.inner(chunks[0]);
-
-        frame.render_widget(block_widget, chunks[0]);
-
-        let centered_paragraph = Paragraph::new("No Cover").alignment(Alignment::Center);
-        let vertical_padding = (block_content_area.height.saturating_sub(1)) / 2;
-        let centered_area = Rect {
-            x: block_content_area.x,
-            y: block_content_area.y + vertical_padding,
-            width: block_content_area.width,
-            height: block_content_area.height.saturating_sub(vertical_padding),
-        };
+        (Some(_), None) => Some(image_placeholder(&Some("Cover".to_string()))),
+        (None, _) => Some("No Cover".to_string()),
+    };
 
-        frame.render_widget(centered_paragraph, centered_area);
+    if let Some(placeholder) = placeholder {
+        render_cover_placeholder(frame, &theme, chunks[0], placeholder);
     }
 }
 
-enum WidgetType<'a> {
-    Paragraph(Paragraph<'a>),
-    Image(String),
+fn render_cover_placeholder(frame: &mut Frame, theme: &Theme, area: Rect, placeholder: String) {
+    let block_widget = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border));
+    let block_content_area = block_widget.inner(area);
+
+    frame.render_widget(block_widget, area);
+
+    let centered_paragraph = Paragraph::new(placeholder).alignment(Alignment::Center);
+    let vertical_padding = (block_content_area.height.saturating_sub(1)) / 2;
+    let centered_area = Rect {
+        x: block_content_area.x,
+        y: block_content_area.y + vertical_padding,
+        width: block_content_area.width,
+        height: block_content_area.height.saturating_sub(vertical_padding),
+    };
+
+    frame.render_widget(centered_paragraph, centered_area);
 }
 
-fn render_reading(frame: &mut Frame, app: &mut App) {
-    let Screen::Reading { page, offset } = &mut app.current_screen else {
+fn render_library(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let Screen::Library {
+        list_state,
+        filter,
+        filtering,
+        sort,
+        prev_screen,
+    } = &mut app.current_screen
+    else {
         unreachable!()
     };
-    let page = app.book.pages.get(page).unwrap();
 
-    let instructions = Paragraph::new(
-        "[I ► Book Info] [Up/Down ► Scroll] [Left/Right ► Navigate Between Chapters] [Q ► Quit]",
-    )
-    .style(Style::default().light_yellow())
-    .alignment(Alignment::Center)
-    .wrap(Wrap { trim: true })
-    .block(Block::new().padding(Padding::horizontal(2)));
+    let instruction = if *filtering {
+        "[Enter ► Apply Filter] [Esc ► Clear Filter]".to_string()
+    } else {
+        let keys = &app.config.keys;
+        format!(
+            "{}[{}/{} ► Navigate] [Enter ► Open] [{} ► Filter] [{} ► Sort] [{} ► Remove] [{} ► Quit]",
+            if prev_screen.is_some() {
+                "[Esc ► Return] "
+            } else {
+                ""
+            },
+            keys.label(Action::ScrollUp),
+            keys.label(Action::ScrollDown),
+            keys.label(Action::Search),
+            keys.label(Action::Sort),
+            keys.label(Action::Delete),
+            keys.label(Action::Quit)
+        )
+    };
+    let instructions = Paragraph::new(instruction)
+        .style(Style::default().fg(theme.instructions))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true })
+        .block(Block::new().padding(Padding::horizontal(2)));
     let instructions_line = instructions.line_count(frame.area().width) as u16;
     let instruction_chunk = Layout::default()
         .direction(Direction::Vertical)
@@ -166,152 +405,1015 @@ fn render_reading(frame: &mut Frame, app: &mut App) {
 
     let main_block = Block::default()
         .borders(Borders::ALL)
-        .title(format!("{}{}", " 📖 Reading - ", page.title))
+        .border_style(Style::new().fg(theme.border))
+        .title(" 📚 Library ")
         .padding(Padding::symmetric(2, 1));
     let main_area = instruction_chunk[0];
     let inner_area = main_block.inner(main_area);
     frame.render_widget(main_block, main_area);
 
-    let mut lines = vec![];
-    let mut content = vec![];
-    let mut widgets: Vec<WidgetType> = vec![];
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(30),
+            Constraint::Length(2),
+            Constraint::Percentage(70),
+        ])
+        .split(inner_area);
 
-    let mut heights: Vec<usize> = vec![];
-    let mut total_height: usize = 0;
+    let right_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(2), Constraint::Min(0)])
+        .split(chunks[2]);
 
-    macro_rules! push_paragraph {
-        ($text:expr) => {{
-            let paragraph = Paragraph::new($text).wrap(Wrap { trim: true });
+    let mut header = vec![Span::styled(
+        format!("Sorted by {}", sort.name()),
+        Style::new().fg(theme.accent),
+    )];
+    if *filtering || !filter.is_empty() {
+        header.push(Span::styled(" · Filter: ", Style::new().fg(theme.accent)));
+        header.push(Span::styled(filter.as_str(), Style::new().bold()));
+        if *filtering {
+            header.push(Span::raw("▏"));
+        }
+    }
+    frame.render_widget(Line::from(header), right_chunks[0]);
 
-            let line_count = paragraph.line_count(inner_area.width);
-            total_height += line_count;
-            heights.push(line_count);
-            widgets.push(WidgetType::Paragraph(paragraph));
-        }};
+    let entries = app.history.entries(filter, *sort);
+    if entries.is_empty() {
+        let message = if app.history.books.is_empty() {
+            "No books yet, run ers with the path to an EPUB file to open one"
+        } else {
+            "No books match the filter"
+        };
+        let placeholder = Paragraph::new(message)
+            .style(Style::new().fg(theme.dim))
+            .wrap(Wrap { trim: true });
+        frame.render_widget(placeholder, right_chunks[1]);
+        render_cover_placeholder(frame, &theme, chunks[0], "No Cover".to_string());
+        return;
     }
 
-    for i in &page.content {
-        match i {
-            ContentType::Text { text, style, .. } => {
-                let mut style_ = Style::new();
-                match style {
-                    TextStyle::Bold => style_ = style_.bold(),
-                    TextStyle::Italic => style_ = style_.italic(),
-                    TextStyle::Underline => style_ = style_.underlined(),
-                    _ => (),
-                }
-                content.push(Span::styled(text, style_));
+    let items: Vec<ListItem> = entries
+        .iter()
+        .map(|entry| {
+            let mut title = vec![Span::styled(
+                entry.title.as_str(),
+                Style::new().bold().fg(theme.entry),
+            )];
+            if let Some(author) = &entry.author {
+                title.push(Span::styled(
+                    format!(" - {}", author),
+                    Style::new().fg(theme.accent),
+                ));
             }
-            ContentType::Image(path) | ContentType::Img(path) => {
-                if !content.is_empty() {
-                    lines.push(Line::from(content.clone()));
-                    content.clear();
-                }
-
-                if !lines.is_empty() {
-                    push_paragraph!(lines.clone());
-                    lines.clear();
-                }
 
-                total_height += inner_area.height as usize;
-                heights.push(inner_area.height as usize);
-                widgets.push(WidgetType::Image(path.clone()));
+            let mut details = vec![Span::styled(
+                format!(
+                    "{:.0}% · Last opened {}",
+                    entry.progress * 100.0,
+                    entry.last_opened_date()
+                ),
+                Style::new().fg(theme.dim),
+            )];
+            if !std::path::Path::new(&entry.path).exists() {
+                details.push(Span::styled(
+                    " · File not found",
+                    Style::new().fg(theme.error),
+                ));
             }
-            ContentType::LineBreak => {
-                lines.push(Line::from(content.clone()));
-                content.clear();
+
+            ListItem::new(vec![Line::from(title), Line::from(details)])
+        })
+        .collect();
+
+    let list = List::new(items)
+        .highlight_style(
+            Style::default()
+                .bg(theme.selection)
+                .fg(theme.highlight_text),
+        )
+        .highlight_symbol(" ► ");
+
+    // Keep the selection on a book when the list gets shorter
+    if list_state.selected().is_none_or(|x| x >= entries.len()) {
+        list_state.select(Some(entries.len() - 1));
+    }
+    let thumbnail = list_state
+        .selected()
+        .and_then(|x| entries.get(x))
+        .and_then(|x| x.thumbnail());
+    frame.render_stateful_widget(list, right_chunks[1], list_state);
+
+    // Render the cover of the selected book
+    let placeholder = match (thumbnail, &mut app.picker) {
+        (Some(path), Some(picker)) => {
+            let id = image_id(&path.to_string_lossy(), "library");
+
+            if let Some(cover_state) = app.image_state.get_mut(&id) {
+                let image = ThreadImage::new(id);
+                frame.render_stateful_widget(image, chunks[0], cover_state);
+                None
+            } else if let Ok(dyn_img) = image::open(&path) {
+                insert_image_state(&mut app.image_state, &app.tx_worker, picker, id, dyn_img);
+                None
+            } else {
+                Some("No Cover".to_string())
             }
         }
-    }
+        (Some(_), None) => Some(image_placeholder(&Some("Cover".to_string()))),
+        (None, _) => Some("No Cover".to_string()),
+    };
 
-    if !content.is_empty() {
-        lines.push(Line::from(content));
+    if let Some(placeholder) = placeholder {
+        render_cover_placeholder(frame, &theme, chunks[0], placeholder);
     }
+}
+
+// The memory used by the state is estimated from the size of the image, as the state keeps both
+// the image and the encoded image
+fn insert_image_state(
+    image_state: &mut MemoryCache<ThreadProtocol>,
+    tx_worker: &Sender<(String, StatefulProtocol, Resize, Rect)>,
+    picker: &mut Picker,
+    id: String,
+    image: DynamicImage,
+) {
+    let size = image.as_bytes().len() * 2;
+    let state = ThreadProtocol::new(tx_worker.clone(), picker.new_resize_protocol(image));
 
-    if !lines.is_empty() {
-        push_paragraph!(lines);
+    image_state.insert(id, state, size);
+}
+
+// The largest resolution in pixels that an image can be shown at
+fn max_image_size(area: Rect, picker: &Picker) -> (u32, u32) {
+    let (width, height) = picker.font_size();
+
+    (
+        area.width as u32 * width as u32,
+        area.height as u32 * height as u32,
+    )
+}
+
+fn render_reading(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let keys = &app.config.keys;
+    let key = |action| keys.label(action);
+    let (navigation, next_mode) = match app.mode {
+        ReadingMode::Scroll => (
+            format!(
+                "[{}/{} ► Scroll]",
+                key(Action::ScrollUp),
+                key(Action::ScrollDown)
+            ),
+            "Page Mode",
+        ),
+        ReadingMode::Paged => (
+            format!(
+                "[{}/{} ► Flip Page]",
+                key(Action::PageUp),
+                key(Action::PageDown)
+            ),
+            "Continuous Mode",
+        ),
+        ReadingMode::Continuous => (
+            format!(
+                "[{}/{} ► Scroll]",
+                key(Action::ScrollUp),
+                key(Action::ScrollDown)
+            ),
+            "Scroll Mode",
+        ),
+    };
+    let search = if app.search_hits.is_empty() {
+        format!("[{} ► Search]", key(Action::Search))
+    } else {
+        format!(
+            "[{} ► Search] [{}/{} ► Next/Previous Match] [{} ► Clear Search]",
+            key(Action::Search),
+            key(Action::NextMatch),
+            key(Action::PreviousMatch),
+            key(Action::ClearSearch)
+        )
+    };
+    let instruction = match &app.selection {
+        Some(selection) if selection.anchor.is_some() => format!(
+            "[{}/{} ► Previous/Next Word] [{}/{} ► Next/Previous Line] [{} ► Restart Selection] [Enter ► Highlight] [Esc ► Cancel]",
+            key(Action::PreviousWord),
+            key(Action::NextWord),
+            key(Action::NextLine),
+            key(Action::PreviousLine),
+            key(Action::StartSelection)
+        ),
+        Some(_) => format!(
+            "[{}/{} ► Previous/Next Word] [{}/{} ► Next/Previous Line] [{} ► Start Selection] [Enter ► Highlight Word] [{} ► Delete Highlight] [Esc ► Cancel]",
+            key(Action::PreviousWord),
+            key(Action::NextWord),
+            key(Action::NextLine),
+            key(Action::PreviousLine),
+            key(Action::StartSelection),
+            key(Action::Delete)
+        ),
+        None => format!(
+            "[{} ► Book Info] {} [{}/{} ► Navigate Between Chapters] [{} ► View Image] [{} ► Choose Image] [{} ► Select] [{}/{} ► Add Bookmark/Bookmarks] {} [{} ► {}] [{} ► Switch Theme] [{}/{} ► Line Width] [{}/{} ► Margins] [{} ► Paragraph Spacing] [{} ► Indent] [{} ► Justify] [{} ► Hyphenation] [{} ► Quit]",
+            key(Action::BookInfo),
+            navigation,
+            key(Action::PreviousChapter),
+            key(Action::NextChapter),
+            key(Action::ViewImage),
+            key(Action::ChooseImage),
+            key(Action::Select),
+            key(Action::AddBookmark),
+            key(Action::Bookmarks),
+            search,
+            key(Action::SwitchMode),
+            next_mode,
+            key(Action::SwitchTheme),
+            key(Action::Narrower),
+            key(Action::Wider),
+            key(Action::DecreaseMargin),
+            key(Action::IncreaseMargin),
+            key(Action::ParagraphSpacing),
+            key(Action::Indent),
+            key(Action::Justify),
+            key(Action::Hyphenate),
+            key(Action::Quit)
+        ),
+    };
+    let instructions = Paragraph::new(instruction)
+        .style(Style::default().fg(theme.instructions))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true })
+        .block(Block::new().padding(Padding::horizontal(2)));
+    let instructions_line = instructions.line_count(frame.area().width) as u16;
+    let instruction_chunk = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage(100),
+            Constraint::Length(1),
+            Constraint::Length(instructions_line),
+        ])
+        .split(frame.area());
+    frame.render_widget(instructions, instruction_chunk[2]);
+
+    let mut main_block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
+        .padding(Padding::symmetric(
+            app.typography.margin,
+            app.config.margins.vertical,
+        ));
+    let main_area = instruction_chunk[0];
+    let view_height = main_block.inner(main_area).height as usize;
+    app.set_reading_area(main_block.inner(main_area));
+    // The column of text in the screen
+    let inner_area = app.reading_area;
+
+    if app.mode == ReadingMode::Continuous {
+        app.normalize_continuous_position();
     }
 
-    // Clamp the offset to make sure it does not exceed the total height of the content
-    if total_height > inner_area.height as usize {
-        *offset = (*offset).min(total_height - inner_area.height as usize);
+    let Screen::Reading { page, offset } = &mut app.current_screen else {
+        unreachable!()
+    };
+    main_block = main_block.title(format!(
+        "{}{}",
+        " 📖 Reading - ",
+        app.book.pages.get(page).unwrap().title
+    ));
+
+    let layout = app.layouts.get(&mut app.book, page, inner_area, app.picker);
+    app.pages = layout.pages().to_vec();
+
+    let mut page_status = None;
+
+    // The rows of the chapter that are shown
+    let (start, end) = match app.mode {
+        ReadingMode::Scroll => {
+            // Clamp the offset to make sure it does not exceed the total height of the content
+            *offset = (*offset).min(layout.height().saturating_sub(view_height));
+
+            (*offset, *offset + view_height)
+        }
+        ReadingMode::Paged => {
+            // The offset is moved to the start of the page that it is on
+            let index = app.pages.partition_point(|x| *x <= *offset) - 1;
+            *offset = app.pages[index];
+
+            page_status = Some(format!("Page {} of {}", index + 1, app.pages.len()));
+
+            let next = app.pages.get(index + 1).copied();
+            (
+                *offset,
+                next.unwrap_or(layout.height()).min(*offset + view_height),
+            )
+        }
+        // The offset can be on the divider after the chapter
+        ReadingMode::Continuous => (*offset, layout.height().min(*offset + view_height)),
+    };
+    frame.render_widget(main_block, main_area);
+    // The section is the one of the top of the screen
+    let top = layout.position(start);
+
+    // The scrollbar is drawn over the right border, and shows the position in the chapter
+    let bottom = layout.height().saturating_sub(view_height);
+    let area = Rect {
+        x: main_area.right().saturating_sub(1),
+        width: 1,
+        ..main_area.inner(Margin::new(0, 1))
+    };
+    app.scrollbar = (bottom > 0 && area.height > 0).then(|| {
+        let mut state = ScrollbarState::new(bottom + 1)
+            .position(start)
+            .viewport_content_length(view_height);
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(None)
+                .end_symbol(None)
+                .track_symbol(Some("│"))
+                .track_style(Style::new().fg(theme.border))
+                .thumb_style(Style::new().fg(theme.accent)),
+            area,
+            &mut state,
+        );
+
+        (area, bottom)
+    });
+
+    // The progress is measured at the bottom of the screen
+    let fraction = if layout.height() == 0 {
+        1.0
     } else {
-        *offset = 0;
+        (end as f64 / layout.height() as f64).min(1.0)
+    };
+    let length = app.book.pages.get(page).unwrap().length;
+    let position = app.book.position(page, fraction);
+    let total = app.book.length().max(1);
+    app.reading_speed.update(position);
+
+    let chapter_left = app
+        .reading_speed
+        .time_left(length - (length as f64 * fraction) as usize);
+    let book_left = app.reading_speed.time_left(total.saturating_sub(position));
+    let progress = [
+        page_status,
+        Some(format!("Chapter {:.0}%", fraction * 100.0)),
+        Some(format!(
+            "Book {:.0}%",
+            position as f64 * 100.0 / total as f64
+        )),
+        Some(format!("{} left in chapter", format_duration(chapter_left))),
+        Some(format!("{} left in book", format_duration(book_left))),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<String>>()
+    .join(" · ");
+
+    let progress = Line::from(format!("{} ", progress)).fg(theme.status);
+    let status_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(progress.width() as u16),
+        ])
+        .split(instruction_chunk[1]);
+    // The note of the highlight under the cursor is shown instead of the section
+    let note = app.selection.as_ref().and_then(|selection| {
+        app.highlights
+            .highlights
+            .iter()
+            .find(|x| x.page == selection.page && (x.start..x.end).contains(&selection.cursor))
+            .and_then(|x| x.note.as_ref())
+    });
+    let section = match note {
+        Some(note) => Line::from(format!(" 📝 {}", note.replace('\n', " "))).fg(theme.title),
+        None => Line::from(format!(
+            " {}",
+            app.book.toc_section(page, top).unwrap_or_default()
+        ))
+        .bold()
+        .fg(theme.status),
+    };
+    frame.render_widget(progress.right_aligned(), status_chunks[1]);
+
+    let mut chapter = page.clone();
+    let mut nearest_distance = usize::MAX;
+    app.nearest_image = None;
+    app.click_targets.clear();
+    render_rows(
+        frame,
+        app,
+        &chapter,
+        start..end,
+        inner_area,
+        &mut nearest_distance,
+    );
+
+    // In continuous mode the following chapters are shown after a divider until the screen is
+    // filled
+    let mut y = end - start;
+    while app.mode == ReadingMode::Continuous && y < view_height {
+        let Some(next) = app.neighbour_chapter(&chapter, true) else {
+            break;
+        };
+
+        let divider = Line::from("─".repeat(inner_area.width as usize / 3))
+            .fg(theme.dim)
+            .centered();
+        frame.render_widget(
+            divider,
+            Rect {
+                y: inner_area.y + y as u16,
+                height: 1,
+                ..inner_area
+            },
+        );
+        y += 1;
+
+        let rows = app.chapter_height(&next).min(view_height - y);
+        let area = Rect {
+            y: inner_area.y + y as u16,
+            height: (view_height - y) as u16,
+            ..inner_area
+        };
+        render_rows(frame, app, &next, 0..rows, area, &mut nearest_distance);
+
+        y += rows;
+        chapter = next;
     }
 
-    let mut reduce_height = 0;
-    let mut current_widget = 0;
-
-    let Rect { x, y, height, .. } = inner_area;
-    let base_x = x as usize;
-    let base_y = y as usize;
-    let base_height = height as usize;
-
-    while reduce_height < *offset + base_height && current_widget < heights.len() {
-        let height = *heights.get(current_widget).unwrap();
-        let mut visible_bottom = false;
-
-        if reduce_height + height > *offset {
-            let (y, widget_height, scroll_offset) = if reduce_height < *offset {
-                // Partially visible at the top
-                let visible_height = (height - (*offset - reduce_height)).min(base_height);
-                (base_y, visible_height, *offset - reduce_height)
-            } else if reduce_height + height > *offset + base_height {
-                // Partially visible at the bottom
-                let visible_height = *offset + base_height - reduce_height;
-                visible_bottom = true;
-                (base_y + reduce_height - *offset, visible_height, 0)
+    // The image chosen to be opened is marked beside its first row, and named instead of the
+    // section, as the images on the screen are only known after the rows are rendered
+    let images = app
+        .click_targets
+        .iter()
+        .filter(|(_, target)| matches!(target, ClickTarget::Image { .. }))
+        .collect::<Vec<_>>();
+    let section = match app.chosen_image.map(|x| images.get(x)) {
+        Some(Some((area, ClickTarget::Image { path, .. }))) => {
+            if area.x >= inner_area.x + 2 {
+                frame.render_widget(
+                    Span::from("►").fg(theme.title),
+                    Rect {
+                        x: area.x - 2,
+                        width: 1,
+                        height: 1,
+                        ..*area
+                    },
+                );
+            }
+
+            Line::from(format!(
+                " Image {}/{} - {}",
+                app.chosen_image.unwrap() + 1,
+                images.len(),
+                path
+            ))
+            .fg(theme.title)
+        }
+        Some(_) => {
+            app.chosen_image = None;
+            section
+        }
+        None => section,
+    };
+    frame.render_widget(section, status_chunks[0]);
+}
+
+// Render the rows of a chapter into the area, and update the image nearest to the shown rows and
+// the links and the images that can be clicked
+fn render_rows(
+    frame: &mut Frame,
+    app: &mut App,
+    page: &str,
+    rows: Range<usize>,
+    area: Rect,
+    nearest_distance: &mut usize,
+) {
+    let theme = app.theme;
+    let layout = app
+        .layouts
+        .get(&mut app.book, page, app.reading_area, app.picker);
+    let Range { start, end } = rows;
+
+    // The highlights, the search hits and the selection in the chapter, the later ranges take
+    // precedence
+    let mut highlights: Vec<(Range<usize>, Style)> = app
+        .highlights
+        .highlights
+        .iter()
+        .filter(|x| x.page == page)
+        .map(|x| (x.start..x.end, theme.highlight(x.color)))
+        .collect();
+    highlights.extend(
+        app.search_hits
+            .iter()
+            .enumerate()
+            .filter(|(index, hit)| hit.page == page && Some(*index) != app.current_hit)
+            .map(|(_, hit)| {
+                (
+                    hit.start..hit.end,
+                    Style::new().fg(theme.highlight_text).bg(theme.search_match),
+                )
+            }),
+    );
+    if let Some(hit) = app.current_hit.map(|x| &app.search_hits[x]) {
+        if hit.page == page {
+            highlights.push((
+                hit.start..hit.end,
+                Style::new()
+                    .fg(theme.highlight_text)
+                    .bg(theme.current_match),
+            ));
+        }
+    }
+    if let Some(selection) = app.selection.as_ref().filter(|x| x.page == page) {
+        highlights.push((selection.range(), Style::new().reversed()));
+    }
+
+    // Find the image nearest to the viewport, so that it can be opened in the viewer
+    for (top, item) in layout.items() {
+        if let Item::Image { path, height, .. } = item {
+            let distance = if top + *height as usize <= start {
+                start - (top + *height as usize) + 1
+            } else if top >= end {
+                top - end + 1
             } else {
-                // Fully visible
-                (base_y + reduce_height - *offset, height, 0)
-            };
-            let rect = Rect {
-                x: base_x as u16,
-                y: y as u16,
-                height: widget_height as u16,
-                ..inner_area
+                0
             };
 
-            match &widgets[current_widget] {
-                WidgetType::Paragraph(paragraph) => {
-                    frame.render_widget(paragraph.clone().scroll((scroll_offset as u16, 0)), rect);
+            if distance < *nearest_distance {
+                *nearest_distance = distance;
+                app.nearest_image = Some((page.to_string(), path.clone()));
+            }
+        }
+    }
+
+    // Only the items in the viewport are rendered
+    for (top, item) in layout.visible_items(start..end) {
+        let visible_top = top.max(start);
+        let visible_bottom = (top + item.height()).min(end);
+        let rect = Rect {
+            y: area.y + (visible_top - start) as u16,
+            height: (visible_bottom - visible_top) as u16,
+            ..area
+        };
+        match item {
+            Item::Line(fragments) => {
+                let content = &app.book.pages.get(page).unwrap().content;
+
+                let mut x = rect.x;
+                for fragment in fragments {
+                    x += fragment.gap as u16;
+                    let width = fragment.text.width() as u16;
+                    if let ContentType::Text {
+                        href: Some(href), ..
+                    } = &content[fragment.content]
+                    {
+                        app.click_targets
+                            .push((Rect { x, width, ..rect }, ClickTarget::Link(href.clone())));
+                    }
+                    x += width;
                 }
-                WidgetType::Image(path) => {
-                    let state = app.image_state.get_mut(path);
-
-                    if state.is_some() {
-                        let thr_img = ThreadImage::new(path.clone()).resize(Resize::Crop(Some(
-                            CropOptions {
-                                clip_left: false,
-                                clip_top: !visible_bottom,
-                            },
-                        )));
-                        let img = app.book.images.get(path).unwrap();
-
-                        frame.render_stateful_widget(
-                            thr_img,
-                            Rect {
-                                x: (rect.width / 2 - img.cal_width(height)).max(rect.x),
-                                ..rect
-                            },
-                            state.unwrap(),
-                        );
+
+                let spans = line_spans(fragments, content, &highlights, &theme);
+                frame.render_widget(Line::from(spans), rect);
+            }
+            Item::Image {
+                path,
+                width,
+                height,
+                ..
+            } => {
+                let image_area = Rect {
+                    x: rect.x + (rect.width - width) / 2,
+                    width: *width,
+                    ..rect
+                };
+                app.click_targets.push((
+                    image_area,
+                    ClickTarget::Image {
+                        page: page.to_string(),
+                        path: path.clone(),
+                    },
+                ));
+
+                // The size is part of the id, as the image is scaled before it is cropped
+                let id = image_id(path, &format!("{}x{}", width, height));
+
+                if let Some(state) = app.image_state.get_mut(&id) {
+                    let thr_img = ThreadImage::new(id).resize(Resize::Crop(Some(CropOptions {
+                        clip_left: false,
+                        clip_top: top < start,
+                    })));
+
+                    frame.render_stateful_widget(thr_img, image_area, state);
+                } else {
+                    let picker = app.picker.as_mut().unwrap();
+                    let (font_width, font_height) = picker.font_size();
+                    let size = (
+                        *width as u32 * font_width as u32,
+                        *height as u32 * font_height as u32,
+                    );
+                    let image = app.decoded_images.get(
+                        &app.book.images,
+                        path,
+                        max_image_size(frame.area(), picker),
+                    );
+                    let dyn_img = if image.width() > size.0 || image.height() > size.1 {
+                        image.resize(size.0, size.1, FilterType::Triangle)
                     } else {
-                        let dyn_img = app.book.images.get_mut(path).unwrap().get();
-
-                        app.image_state.insert(
-                            path.clone(),
-                            ThreadProtocol::new(
-                                app.tx_worker.clone(),
-                                app.picker.new_resize_protocol(dyn_img.clone()),
-                            ),
-                        );
-                    }
+                        image.clone()
+                    };
+
+                    insert_image_state(&mut app.image_state, &app.tx_worker, picker, id, dyn_img);
+                }
+            }
+        }
+    }
+}
+
+// The spans of a line, with the style of the highlighted ranges of the text of the page patched
+// in. The later ranges take precedence.
+fn line_spans<'a>(
+    fragments: &'a [Fragment],
+    content: &[ContentType],
+    highlights: &[(Range<usize>, Style)],
+    theme: &Theme,
+) -> Vec<Span<'a>> {
+    let mut spans = vec![];
+
+    for fragment in fragments {
+        let style = theme.content_style(&content[fragment.content]);
+        if fragment.gap > 0 {
+            spans.push(Span::raw(" ".repeat(fragment.gap)));
+        }
+
+        // Image placeholders are not part of the text
+        if highlights.is_empty() || !matches!(content[fragment.content], ContentType::Text { .. }) {
+            spans.push(Span::styled(fragment.text.as_str(), style));
+            continue;
+        }
+
+        let mut start = 0;
+        let mut current = None;
+        for (index, (byte, _)) in fragment.text.char_indices().enumerate() {
+            let position = fragment.position + index;
+            let highlighted = highlights
+                .iter()
+                .rev()
+                .find(|(range, _)| range.contains(&position))
+                .map(|(_, highlight)| style.patch(*highlight))
+                .unwrap_or(style);
+
+            match current {
+                Some(x) if x != highlighted => {
+                    spans.push(Span::styled(&fragment.text[start..byte], x));
+                    start = byte;
+                    current = Some(highlighted);
                 }
+                Some(_) => (),
+                None => current = Some(highlighted),
             }
         }
 
-        reduce_height += height;
-        current_widget += 1;
+        if let Some(style) = current {
+            spans.push(Span::styled(&fragment.text[start..], style));
+        }
+    }
+
+    spans
+}
+
+fn render_highlight_prompt(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let prompt = app.highlight_prompt.as_ref().unwrap();
+
+    let area = frame.area();
+    let popup_area = popup_area(area, 70.min(area.width), 10);
+    frame.render_widget(Clear, popup_area);
+
+    // The popup is cleared, so the background of the theme is set again
+    let popup_block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
+        .style(Style::new().fg(theme.text).bg(theme.background))
+        .title(" 🖍️ Highlight ")
+        .title_bottom(
+            Line::from(" [Tab ► Color] [Ctrl+E ► Open Editor] [Enter ► Save] [Esc ► Cancel] ")
+                .fg(theme.instructions)
+                .centered(),
+        )
+        .padding(Padding::horizontal(1));
+    let inner_area = popup_block.inner(popup_area);
+    frame.render_widget(popup_block, popup_area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .split(inner_area);
+
+    let color = Line::from(vec![
+        Span::raw("Color: "),
+        Span::styled(
+            format!(" {} ", prompt.color.name()),
+            theme.highlight(prompt.color),
+        ),
+    ]);
+    frame.render_widget(color, chunks[0]);
+    frame.render_widget(Line::from("Note:").fg(theme.dim), chunks[1]);
+
+    let note = Paragraph::new(format!("{}█", prompt.note)).wrap(Wrap { trim: false });
+    frame.render_widget(note, chunks[2]);
+}
+
+// An area in the middle of the screen
+fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
+    let (width, height) = (width.min(area.width), height.min(area.height));
+
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+fn render_bookmark_prompt(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let name = app.bookmark_prompt.as_ref().unwrap();
+
+    let popup_area = popup_area(frame.area(), 60, 3);
+    frame.render_widget(Clear, popup_area);
+
+    // The popup is cleared, so the background of the theme is set again
+    let popup_block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
+        .style(Style::new().fg(theme.text).bg(theme.background))
+        .title(" 🔖 Add Bookmark ")
+        .title_bottom(
+            Line::from(" [Enter ► Save] [Esc ► Cancel] ")
+                .fg(theme.instructions)
+                .centered(),
+        )
+        .padding(Padding::horizontal(1));
+    let inner_area = popup_block.inner(popup_area);
+    frame.render_widget(popup_block, popup_area);
+
+    let input = if name.is_empty() {
+        Line::from(vec![
+            Span::raw("█"),
+            Span::styled(
+                "Name, or empty for the chapter title",
+                Style::new().fg(theme.dim),
+            ),
+        ])
+    } else {
+        Line::from(vec![Span::raw(name.as_str()), Span::raw("█")])
+    };
+    frame.render_widget(input, inner_area);
+}
+
+fn render_search(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let prompt = app.search_prompt.as_mut().unwrap();
+
+    let area = frame.area();
+    let popup_area = popup_area(area, area.width * 4 / 5, area.height * 3 / 5);
+    frame.render_widget(Clear, popup_area);
+
+    // The popup is cleared, so the background of the theme is set again
+    let popup_block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
+        .style(Style::new().fg(theme.text).bg(theme.background))
+        .title(" 🔍 Search ")
+        .title_bottom(
+            Line::from(" [Enter ► Search/Go To Match] [Tab ► Toggle Regex] [Esc ► Close] ")
+                .fg(theme.instructions)
+                .centered(),
+        )
+        .padding(Padding::horizontal(1));
+    let inner_area = popup_block.inner(popup_area);
+    frame.render_widget(popup_block, popup_area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .split(inner_area);
+
+    let input = Line::from(vec![
+        Span::styled("/", Style::new().fg(theme.accent)),
+        Span::raw(prompt.query.as_str()),
+        Span::raw("█"),
+    ]);
+    frame.render_widget(input, chunks[0]);
+
+    let mode = if prompt.regex { "Regex" } else { "Text" };
+    let status = match &prompt.error {
+        Some(err) => Line::from(err.lines().last().unwrap_or_default().to_string()).fg(theme.error),
+        None if prompt.searched => {
+            Line::from(format!("{} · {} results", mode, app.search_hits.len())).fg(theme.dim)
+        }
+        None => Line::from(format!("{} · Press Enter to search", mode)).fg(theme.dim),
+    };
+    frame.render_widget(status, chunks[1]);
+
+    let results: Vec<ListItem> = app
+        .search_hits
+        .iter()
+        .map(|hit| {
+            ListItem::from(Line::from(vec![
+                Span::styled(
+                    format!("{}: ", app.book.pages.get(&hit.page).unwrap().title),
+                    Style::new().bold().fg(theme.entry),
+                ),
+                Span::raw(hit.context.0.as_str()),
+                Span::styled(
+                    hit.context.1.as_str(),
+                    Style::new().fg(theme.highlight_text).bg(theme.search_match),
+                ),
+                Span::raw(hit.context.2.as_str()),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(results)
+        .highlight_style(
+            Style::default()
+                .bg(theme.selection)
+                .fg(theme.highlight_text),
+        )
+        .highlight_symbol(" ► ");
+    frame.render_stateful_widget(list, chunks[2], &mut prompt.results);
+}
+
+// Duration rounded up to minutes, like "1 h 5 min"
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs().div_ceil(60);
+
+    if minutes < 60 {
+        format!("{} min", minutes)
+    } else {
+        format!("{} h {} min", minutes / 60, minutes % 60)
+    }
+}
+
+// Prefix of the variants of the image states used by the viewer
+const VIEWER_ID: &str = "viewer";
+// Largest width and height in pixels of an image shown at actual size, as larger images would take
+// too much memory to decode
+const MAX_ACTUAL_SIZE: u32 = 8192;
+
+fn render_viewer(frame: &mut Frame, app: &mut App) {
+    let theme = app.theme;
+    let Screen::Viewer {
+        images,
+        index,
+        zoom,
+        pan,
+        ..
+    } = &mut app.current_screen
+    else {
+        unreachable!()
+    };
+    let path = images.get(*index).unwrap();
+
+    let keys = &app.config.keys;
+    let instruction = match zoom {
+        Zoom::Fit => format!(
+            "[Esc ► Return] [{} ► Actual Size] [{}/{} ► Next/Previous Image] [{} ► Quit]",
+            keys.label(Action::Zoom),
+            keys.label(Action::NextImage),
+            keys.label(Action::PreviousImage),
+            keys.label(Action::Quit)
+        ),
+        Zoom::Actual => format!(
+            "[Esc ► Return] [{} ► Fit to Screen] [{}/{}/{}/{} ► Pan] [{}/{} ► Next/Previous Image] [{} ► Quit]",
+            keys.label(Action::Zoom),
+            keys.label(Action::PanLeft),
+            keys.label(Action::PanDown),
+            keys.label(Action::PanUp),
+            keys.label(Action::PanRight),
+            keys.label(Action::NextImage),
+            keys.label(Action::PreviousImage),
+            keys.label(Action::Quit)
+        ),
+    };
+    let instructions = Paragraph::new(instruction)
+        .style(Style::default().fg(theme.instructions))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true })
+        .block(Block::new().padding(Padding::horizontal(2)));
+    let instructions_line = instructions.line_count(frame.area().width) as u16;
+    let instruction_chunk = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage(100),
+            Constraint::Length(instructions_line),
+        ])
+        .split(frame.area());
+    frame.render_widget(instructions, instruction_chunk[1]);
+
+    let main_block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().fg(theme.border))
+        .title(format!(
+            " 🖼️ Image {}/{} - {} ",
+            *index + 1,
+            images.len(),
+            path
+        ));
+    let main_area = instruction_chunk[0];
+    let inner_area = main_block.inner(main_area);
+    frame.render_widget(main_block, main_area);
+
+    // The viewer is only opened from images shown on the screen, but the picker is checked in
+    // case the images are hidden
+    let Some(picker) = app.picker.as_mut() else {
+        frame.render_widget(
+            Paragraph::new(image_placeholder(&Some(path.clone())))
+                .fg(theme.dim)
+                .alignment(Alignment::Center),
+            inner_area,
+        );
+        return;
+    };
+    let (font_width, font_height) = picker.font_size();
+    let view_size = (
+        inner_area.width as u32 * font_width as u32,
+        inner_area.height as u32 * font_height as u32,
+    );
+    // Images are only decoded at their native resolution when shown at actual size, which is the
+    // intrinsic size of SVG images
+    let max_size = match zoom {
+        Zoom::Fit => max_image_size(frame.area(), picker),
+        Zoom::Actual => {
+            let (width, height) = app.book.images.get_mut(path).unwrap().dimensions();
+            (width.min(MAX_ACTUAL_SIZE), height.min(MAX_ACTUAL_SIZE))
+        }
+    };
+    let image = app.decoded_images.get(&app.book.images, path, max_size);
+    let (width, height) = image.dimensions();
+
+    // Make sure that the pan does not go beyond the edges of the image
+    pan.0 = pan.0.min(width.saturating_sub(view_size.0));
+    pan.1 = pan.1.min(height.saturating_sub(view_size.1));
+
+    // Size in pixels of the image shown on the screen
+    let shown_size = match zoom {
+        Zoom::Fit => {
+            let scale = (view_size.0 as f32 / width as f32).min(view_size.1 as f32 / height as f32);
+            (
+                (width as f32 * scale) as u32,
+                (height as f32 * scale) as u32,
+            )
+        }
+        Zoom::Actual => (width.min(view_size.0), height.min(view_size.1)),
+    };
+
+    let id = match zoom {
+        Zoom::Fit => image_id(path, VIEWER_ID),
+        Zoom::Actual => image_id(path, &format!("{}{},{}", VIEWER_ID, pan.0, pan.1)),
+    };
+
+    if let Some(state) = app.image_state.get_mut(&id) {
+        let columns = (shown_size.0.div_ceil(font_width as u32) as u16).min(inner_area.width);
+        let rows = (shown_size.1.div_ceil(font_height as u32) as u16).min(inner_area.height);
+
+        frame.render_stateful_widget(
+            ThreadImage::new(id),
+            Rect {
+                x: inner_area.x + (inner_area.width - columns) / 2,
+                y: inner_area.y + (inner_area.height - rows) / 2,
+                width: columns,
+                height: rows,
+            },
+            state,
+        );
+    } else {
+        let shown = match zoom {
+            // Only enlarge here, shrinking is done in the background by the protocol
+            Zoom::Fit if width < view_size.0 && height < view_size.1 => {
+                image.resize(view_size.0, view_size.1, FilterType::Triangle)
+            }
+            Zoom::Fit => image.clone(),
+            Zoom::Actual => image.crop_imm(pan.0, pan.1, shown_size.0, shown_size.1),
+        };
+
+        // Only keep the state of the image currently shown in the viewer
+        app.image_state
+            .retain(|k| !image_id_parts(k).1.starts_with(VIEWER_ID));
+        insert_image_state(&mut app.image_state, &app.tx_worker, picker, id, shown);
     }
 }